@@ -0,0 +1,296 @@
+use anyhow::{Context, Result}; // Importing Result and Context from anyhow crate
+use async_trait::async_trait;
+use log::debug;
+use regex::Regex;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::fs;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+use crate::library::feature::LlmConfig;
+use crate::library::prompt::{build_provider, LlmProvider, Message};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CassetteMode {
+    // Every call goes out live and is saved
+    Record,
+    // Every call is answered from disk, and one that wasn't recorded fails
+    Replay,
+    // Recorded calls are answered from disk, the rest go out live and are saved
+    ReplayOrRecord,
+}
+
+// One recorded call and what came back
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Interaction {
+    kind: String,
+    // As normalised for the key
+    request: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    response: Option<Value>,
+    // Failed calls are recorded too, so a failing run replays the same way
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+// LLM and service calls saved to a directory, one JSON file per call, named
+// by the kind of call, a hash of the normalised request and how many times
+// the same request came before it in the run
+#[derive(Debug)]
+pub struct Cassette {
+    mode: CassetteMode,
+    dir: PathBuf,
+    // Text that differs between runs, such as the checkout's path, and what it is keyed as
+    substitutions: Mutex<Vec<(String, String)>>,
+    // How many times each request has been made so far
+    seen: Mutex<HashMap<String, usize>>,
+}
+
+impl Cassette {
+    pub fn new(mode: CassetteMode, dir: PathBuf) -> Self {
+        Cassette {
+            mode,
+            dir,
+            substitutions: Mutex::default(),
+            seen: Mutex::default(),
+        }
+    }
+
+    pub fn mode(&self) -> CassetteMode {
+        self.mode
+    }
+
+    // Requests containing `text` are keyed as if they said `placeholder` instead
+    pub fn substitute(&self, text: &str, placeholder: &str) {
+        if !text.is_empty() {
+            self.substitutions.lock().unwrap().push((text.to_string(), placeholder.to_string()));
+        }
+    }
+
+    // Answers the request from disk when it was recorded and the mode allows,
+    // otherwise makes it with `live` and saves the result unless replaying
+    pub async fn call<T, F, Fut>(&self, kind: &str, request: Value, live: F) -> Result<T>
+    where
+        T: Serialize + DeserializeOwned,
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let request = self.normalize(&request);
+        let path = self.path(kind, &request, true);
+        if self.mode != CassetteMode::Record && path.is_file() {
+            debug!("Replaying {} from {}", kind, path.display());
+            return replay(&path);
+        }
+        if self.mode == CassetteMode::Replay {
+            anyhow::bail!(
+                "{} has no recording of this {} request (looked for {}). The run has changed since it was recorded; record it again with --record or --replay-or-record.",
+                self.dir.display(), kind, path.file_name().unwrap_or_default().to_string_lossy()
+            );
+        }
+
+        let result = live().await;
+        let interaction = Interaction {
+            kind: kind.to_string(),
+            request,
+            response: result.as_ref().ok().map(serde_json::to_value).transpose()?,
+            error: result.as_ref().err().map(|err| format!("{:#}", err)),
+        };
+        fs::create_dir_all(&self.dir)
+            .with_context(|| format!("Failed to create cassette directory {}", self.dir.display()))?;
+        fs::write(&path, serde_json::to_string_pretty(&interaction)? + "\n")
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        result
+    }
+
+    // Whether the next such request would be answered from disk, without counting it as made
+    pub fn will_replay(&self, kind: &str, request: &Value) -> bool {
+        self.mode != CassetteMode::Record && self.path(kind, &self.normalize(request), false).is_file()
+    }
+
+    fn path(&self, kind: &str, request: &Value, count: bool) -> PathBuf {
+        let hash = format!("{:016x}", fnv1a(request.to_string().as_bytes()));
+        let key = format!("{}-{}", kind, hash);
+        let mut seen = self.seen.lock().unwrap();
+        let occurrence = seen.get(&key).copied().unwrap_or(0);
+        if count {
+            seen.insert(key.clone(), occurrence + 1);
+        }
+        self.dir.join(format!("{}-{}.json", key, occurrence))
+    }
+
+    // The request with its object keys sorted and the parts that change from
+    // run to run replaced: substituted text, localhost ports and line endings
+    fn normalize(&self, request: &Value) -> Value {
+        let substitutions = self.substitutions.lock().unwrap().clone();
+        let port = localhost_port();
+        normalize_value(request, &|text: &str| {
+            let mut text = text.replace("\r\n", "\n");
+            for (from, to) in &substitutions {
+                text = text.replace(from, to);
+            }
+            port.replace_all(&text, "$1:PORT").lines().map(str::trim_end).collect::<Vec<_>>().join("\n")
+        })
+    }
+}
+
+// Goes through `cassette` when there is one, or straight to `live`
+pub async fn recorded<T, F, Fut>(cassette: Option<&Cassette>, kind: &str, request: Value, live: F) -> Result<T>
+where
+    T: Serialize + DeserializeOwned,
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    match cassette {
+        Some(cassette) => cassette.call(kind, request, live).await,
+        None => live().await,
+    }
+}
+
+// The provider for `config`, with its calls going through `cassette`. When
+// only replaying, no live provider is built, so no API key is needed.
+pub fn cassette_provider(config: &LlmConfig, cassette: Arc<Cassette>) -> Result<Box<dyn LlmProvider>> {
+    let inner = match cassette.mode() {
+        CassetteMode::Replay => None,
+        _ => Some(build_provider(config)?),
+    };
+    // Replies depend on the model and its sampling, not on where it is served
+    let model = json!({
+        "provider": config.provider,
+        "model": config.model,
+        "temperature": config.temperature,
+        "maxTokens": config.max_tokens,
+    });
+    Ok(Box::new(CassetteProvider { inner, cassette, model }))
+}
+
+struct CassetteProvider {
+    inner: Option<Box<dyn LlmProvider>>,
+    cassette: Arc<Cassette>,
+    model: Value,
+}
+
+impl CassetteProvider {
+    fn live(&self) -> Result<&dyn LlmProvider> {
+        self.inner.as_deref().context("The model can't be called while replaying")
+    }
+}
+
+#[async_trait]
+impl LlmProvider for CassetteProvider {
+    async fn chat(&self, messages: &[Message]) -> Result<String> {
+        let request = json!({ "model": self.model, "messages": messages });
+        self.cassette.call("llm", request, || async { self.live()?.chat(messages).await }).await
+    }
+
+    async fn chat_structured(&self, messages: &[Message], name: &str, schema: &Value) -> Result<String> {
+        let request = json!({ "model": self.model, "messages": messages, "name": name, "schema": schema });
+        self.cassette.call("llm", request, || async { self.live()?.chat_structured(messages, name, schema).await }).await
+    }
+}
+
+fn replay<T: DeserializeOwned>(path: &Path) -> Result<T> {
+    let text = fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let interaction: Interaction = serde_json::from_str(&text)
+        .with_context(|| format!("Failed to read the recording in {}", path.display()))?;
+    match (interaction.response, interaction.error) {
+        (_, Some(error)) => Err(anyhow::anyhow!(error)),
+        (Some(response), None) => serde_json::from_value(response)
+            .with_context(|| format!("The recording in {} doesn't match what the call returns", path.display())),
+        (None, None) => anyhow::bail!("The recording in {} has neither a response nor an error", path.display()),
+    }
+}
+
+// Object keys are rebuilt in sorted order, since serde_json keeps insertion order here
+fn normalize_value(value: &Value, normalize_text: &dyn Fn(&str) -> String) -> Value {
+    match value {
+        Value::String(text) => Value::String(normalize_text(text)),
+        Value::Array(items) => Value::Array(items.iter().map(|item| normalize_value(item, normalize_text)).collect()),
+        Value::Object(object) => {
+            let mut keys: Vec<&String> = object.keys().collect();
+            keys.sort();
+            let mut sorted = Map::new();
+            for key in keys {
+                sorted.insert(key.clone(), normalize_value(&object[key], normalize_text));
+            }
+            Value::Object(sorted)
+        }
+        other => other.clone(),
+    }
+}
+
+fn localhost_port() -> &'static Regex {
+    static REGEX: OnceLock<Regex> = OnceLock::new();
+    REGEX.get_or_init(|| Regex::new(r"(localhost|127\.0\.0\.1|\[::1\]):\d+").unwrap())
+}
+
+// 64-bit FNV-1a, which unlike std's hasher is the same in every Rust release
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &byte| (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("autocode-cassette-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    // Calls `cassette` with a live call that counts how often it runs
+    async fn call(cassette: &Cassette, request: Value, reply: &str, live_calls: &AtomicUsize) -> Result<String> {
+        cassette.call("llm", request, || async {
+            live_calls.fetch_add(1, Ordering::SeqCst);
+            Ok(reply.to_string())
+        }).await
+    }
+
+    #[tokio::test]
+    async fn recorded_calls_replay_in_order() {
+        let dir = scratch_dir("round-trip");
+        let live_calls = AtomicUsize::new(0);
+        let recorder = Cassette::new(CassetteMode::Record, dir.clone());
+        recorder.substitute("/tmp/checkout-1", "<repo>");
+        let request = json!({ "path": "/tmp/checkout-1/page.js", "url": "http://localhost:3001/" });
+        assert_eq!(call(&recorder, request.clone(), "first", &live_calls).await.unwrap(), "first");
+        assert_eq!(call(&recorder, request, "second", &live_calls).await.unwrap(), "second");
+        let mut files: Vec<String> = fs::read_dir(&dir).unwrap().map(|entry| entry.unwrap().file_name().to_string_lossy().to_string()).collect();
+        files.sort();
+        assert_eq!(files.len(), 2);
+        assert!(files[0].starts_with("llm-") && files[0].ends_with("-0.json"), "{:?}", files);
+        assert_eq!(files[1], files[0].replace("-0.json", "-1.json"));
+
+        // Another checkout, port and line ending make the same keys
+        let replayer = Cassette::new(CassetteMode::Replay, dir.clone());
+        replayer.substitute("/tmp/checkout-2", "<repo>");
+        let request = json!({ "url": "http://localhost:4123/", "path": "/tmp/checkout-2/page.js" });
+        assert!(replayer.will_replay("llm", &request));
+        assert_eq!(call(&replayer, request.clone(), "live", &live_calls).await.unwrap(), "first");
+        assert_eq!(call(&replayer, request.clone(), "live", &live_calls).await.unwrap(), "second");
+        let err = call(&replayer, request, "live", &live_calls).await.unwrap_err();
+        assert!(format!("{:#}", err).contains("has no recording of this llm request"), "{:#}", err);
+        assert_eq!(live_calls.load(Ordering::SeqCst), 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn failed_calls_replay_as_failures() {
+        let dir = scratch_dir("errors");
+        let recorder = Cassette::new(CassetteMode::ReplayOrRecord, dir.clone());
+        let recorded: Result<String> = recorder.call("log-and-run", json!({ "testPath": "/" }), || async { anyhow::bail!("Status code: 500") }).await;
+        assert!(recorded.is_err());
+
+        let replayer = Cassette::new(CassetteMode::ReplayOrRecord, dir.clone());
+        let replayed: Result<String> = replayer.call("log-and-run", json!({ "testPath": "/" }), || async { Ok("live".to_string()) }).await;
+        assert_eq!(format!("{:#}", replayed.unwrap_err()), "Status code: 500");
+        let text = "line one\r\nline two  \r\n";
+        let normalized = replayer.normalize(&json!({ "b": text, "a": 1 }));
+        assert_eq!(normalized.to_string(), r#"{"a":1,"b":"line one\nline two"}"#);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
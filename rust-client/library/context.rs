@@ -0,0 +1,285 @@
+use anyhow::{Context, Result}; // Importing Result and Context from anyhow crate
+use log::{debug, warn};
+use oxc_allocator::Allocator;
+use oxc_ast::ast::Statement;
+use oxc_parser::Parser;
+use serde_json::Value;
+use std::collections::{HashSet, VecDeque};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use crate::library::feature::FileRef;
+use crate::library::prompt_budget::TokenCounter;
+use crate::library::syntax::source_type_for;
+
+// Tried in order for imports written without an extension
+const RESOLVE_EXTENSIONS: [&str; 6] = ["tsx", "ts", "jsx", "js", "mjs", "cjs"];
+// Imports of the target are depth 1, their imports depth 2
+const MAX_DEPTH: usize = 2;
+
+// A file reached from the target through its imports
+#[derive(Debug, Clone)]
+struct Neighbour {
+    // Relative to the repo, with forward slashes
+    path: String,
+    depth: usize,
+    // How many bindings the importing file takes from it
+    imported_names: usize,
+    contents: String,
+}
+
+// A tsconfig or jsconfig `paths` mapping, e.g. "@/*" to ["./src/*"]
+#[derive(Debug, Clone)]
+struct PathAlias {
+    pattern: String,
+    replacements: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Aliases {
+    // Relative to the repo; paths and bare imports resolve from here
+    base_url: Option<PathBuf>,
+    paths: Vec<PathAlias>,
+}
+
+// Files the target imports, directly or through one other file, that aren't
+// already `listed`. Direct imports come first, then files the target takes more
+// names from, then smaller files, and they are added while they fit `budget`.
+pub fn discover_context(repo_dir: &Path, target: &str, listed: &[String], budget: usize, counter: &TokenCounter) -> Result<Vec<FileRef>> {
+    let aliases = load_aliases(repo_dir);
+    let target = normalize(Path::new(target)).with_context(|| format!("{} is outside the repository", target))?;
+    let mut seen: HashSet<PathBuf> = listed.iter().filter_map(|path| normalize(Path::new(path))).collect();
+    seen.insert(target.clone());
+
+    let mut neighbours = Vec::new();
+    let mut queue = VecDeque::from([(target, 0)]);
+    while let Some((file, depth)) = queue.pop_front() {
+        let Ok(contents) = fs::read_to_string(repo_dir.join(&file)) else {
+            continue;
+        };
+        for (specifier, imported_names) in imports(&contents, &file) {
+            let Some(resolved) = resolve(repo_dir, &file, &specifier, &aliases) else {
+                continue;
+            };
+            if !seen.insert(resolved.clone()) {
+                continue;
+            }
+            let Ok(neighbour_contents) = fs::read_to_string(repo_dir.join(&resolved)) else {
+                continue;
+            };
+            if depth + 1 < MAX_DEPTH {
+                queue.push_back((resolved.clone(), depth + 1));
+            }
+            neighbours.push(Neighbour {
+                path: resolved.to_string_lossy().replace('\\', "/"),
+                depth: depth + 1,
+                imported_names,
+                contents: neighbour_contents,
+            });
+        }
+    }
+
+    neighbours.sort_by_key(|neighbour| (neighbour.depth, std::cmp::Reverse(neighbour.imported_names), neighbour.contents.len()));
+    let mut remaining = budget;
+    let mut files = Vec::new();
+    for neighbour in neighbours {
+        let tokens = counter.count(&neighbour.contents);
+        if tokens > remaining {
+            debug!("Leaving {} out of the context, it needs {} tokens and {} are left", neighbour.path, tokens, remaining);
+            continue;
+        }
+        remaining -= tokens;
+        files.push(FileRef {
+            file_name: neighbour.path.clone(),
+            file_path: neighbour.path,
+            file_contents: Some(neighbour.contents),
+            is_target: false,
+        });
+    }
+    Ok(files)
+}
+
+// The module specifiers a file imports or re-exports from, with how many names
+// each import binds
+fn imports(code: &str, file: &Path) -> Vec<(String, usize)> {
+    let allocator = Allocator::default();
+    let parsed = Parser::new(&allocator, code, source_type_for(&file.to_string_lossy())).parse();
+    let mut imports = Vec::new();
+    for statement in &parsed.program.body {
+        match statement {
+            Statement::ImportDeclaration(import) => {
+                let names = import.specifiers.as_ref().map(|specifiers| specifiers.len()).unwrap_or(0);
+                imports.push((import.source.value.to_string(), names));
+            }
+            Statement::ExportNamedDeclaration(export) => {
+                if let Some(source) = &export.source {
+                    imports.push((source.value.to_string(), export.specifiers.len()));
+                }
+            }
+            Statement::ExportAllDeclaration(export) => imports.push((export.source.value.to_string(), 1)),
+            _ => {}
+        }
+    }
+    imports
+}
+
+// The repo file an import refers to, or None for packages and anything that
+// can't be found inside the repo
+fn resolve(repo_dir: &Path, from: &Path, specifier: &str, aliases: &Aliases) -> Option<PathBuf> {
+    let mut bases = Vec::new();
+    if specifier.starts_with("./") || specifier.starts_with("../") {
+        bases.push(from.parent().unwrap_or(Path::new("")).join(specifier));
+    } else {
+        let base_url = aliases.base_url.clone().unwrap_or_default();
+        for alias in &aliases.paths {
+            if let Some(star) = match_alias(&alias.pattern, specifier) {
+                bases.extend(alias.replacements.iter().map(|replacement| base_url.join(replacement.replace('*', star))));
+            }
+        }
+        if aliases.base_url.is_some() {
+            bases.push(base_url.join(specifier));
+        }
+    }
+
+    for base in bases {
+        let Some(base) = normalize(&base) else {
+            continue;
+        };
+        if base.components().any(|component| component.as_os_str() == "node_modules") {
+            continue;
+        }
+        let has_code_extension = base.extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| RESOLVE_EXTENSIONS.contains(&extension));
+        let mut candidates = Vec::new();
+        if has_code_extension {
+            candidates.push(base.clone());
+        }
+        for extension in RESOLVE_EXTENSIONS {
+            let mut with_extension = base.clone().into_os_string();
+            with_extension.push(format!(".{}", extension));
+            candidates.push(PathBuf::from(with_extension));
+        }
+        for extension in RESOLVE_EXTENSIONS {
+            candidates.push(base.join(format!("index.{}", extension)));
+        }
+        if let Some(found) = candidates.into_iter().find(|candidate| repo_dir.join(candidate).is_file()) {
+            return Some(found);
+        }
+    }
+    None
+}
+
+// What `*` stands for when `specifier` matches `pattern`, or "" for an exact match
+fn match_alias<'a>(pattern: &str, specifier: &'a str) -> Option<&'a str> {
+    match pattern.split_once('*') {
+        Some((prefix, suffix)) => {
+            let middle = specifier.strip_prefix(prefix)?.strip_suffix(suffix)?;
+            Some(middle)
+        }
+        None => (pattern == specifier).then_some(""),
+    }
+}
+
+// `compilerOptions.baseUrl` and `paths` from the repo's tsconfig.json, or its
+// jsconfig.json for JavaScript projects
+fn load_aliases(repo_dir: &Path) -> Aliases {
+    for config_name in ["tsconfig.json", "jsconfig.json"] {
+        let Ok(text) = fs::read_to_string(repo_dir.join(config_name)) else {
+            continue;
+        };
+        let config: Value = match serde_json::from_str(&strip_jsonc(&text)) {
+            Ok(config) => config,
+            Err(err) => {
+                warn!("Could not read {}, so import aliases won't be followed: {}", config_name, err);
+                return Aliases::default();
+            }
+        };
+        let options = &config["compilerOptions"];
+        let base_url = options["baseUrl"].as_str().and_then(|base_url| normalize(Path::new(base_url)));
+        let paths = options["paths"].as_object()
+            .map(|paths| paths.iter()
+                .map(|(pattern, replacements)| PathAlias {
+                    pattern: pattern.clone(),
+                    replacements: replacements.as_array()
+                        .map(|replacements| replacements.iter().filter_map(|replacement| replacement.as_str().map(str::to_string)).collect())
+                        .unwrap_or_default(),
+                })
+                .collect())
+            .unwrap_or_default();
+        return Aliases { base_url, paths };
+    }
+    Aliases::default()
+}
+
+// tsconfig files allow comments and trailing commas, which serde_json doesn't
+fn strip_jsonc(text: &str) -> String {
+    let mut without_comments = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    let mut in_string = false;
+    while let Some(c) = chars.next() {
+        if in_string {
+            without_comments.push(c);
+            match c {
+                '\\' => without_comments.extend(chars.next()),
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match (c, chars.peek()) {
+            ('/', Some('/')) => {
+                while chars.next_if(|&next| next != '\n').is_some() {}
+            }
+            ('/', Some('*')) => {
+                chars.next();
+                let mut previous = ' ';
+                for next in chars.by_ref() {
+                    if previous == '*' && next == '/' {
+                        break;
+                    }
+                    previous = next;
+                }
+            }
+            _ => {
+                in_string = c == '"';
+                without_comments.push(c);
+            }
+        }
+    }
+
+    // Commas are only dropped outside strings, before a closing bracket
+    let mut stripped = String::with_capacity(without_comments.len());
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, c) in without_comments.char_indices() {
+        if in_string {
+            in_string = escaped || c != '"';
+            escaped = !escaped && c == '\\';
+        } else if c == '"' {
+            in_string = true;
+        } else if c == ',' && without_comments[i + 1..].trim_start().starts_with(['}', ']']) {
+            continue;
+        }
+        stripped.push(c);
+    }
+    stripped
+}
+
+// Resolves `.` and `..` without touching the filesystem, or None if the path
+// leaves the repo
+fn normalize(path: &Path) -> Option<PathBuf> {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => normalized.push(part),
+            Component::ParentDir => {
+                if !normalized.pop() {
+                    return None;
+                }
+            }
+            Component::CurDir => {}
+            Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+    Some(normalized)
+}
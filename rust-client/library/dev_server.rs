@@ -0,0 +1,273 @@
+use anyhow::{Context, Result}; // Importing Result and Context from anyhow crate
+use log::{debug, error, warn};
+use regex::Regex;
+use std::collections::VecDeque;
+use std::net::TcpListener;
+use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::process::{Child, Command};
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::{RecvError, TryRecvError};
+use tokio::task::JoinHandle;
+
+// Next.js dev output after a successful (re)compile, e.g. " ✓ Compiled /about in 1.2s"
+const COMPILE_SUCCESS: &str = r"(?i)(✓ compiled|compiled successfully|compiled client and server|✓ ready|ready - started server)";
+// Build errors, e.g. "Failed to compile", "Module not found" or " ⨯ ./app/page.js"
+const COMPILE_ERROR: &str = r"(?i)(failed to compile|module not found|^\s*(⨯|error -) \.{0,2}/)";
+// Lines of an error report collected after its first line
+const MAX_ERROR_LINES: usize = 40;
+
+#[derive(Debug, Clone)]
+pub struct DevServerConfig {
+    pub program: String,
+    pub args: Vec<String>,
+    pub dir: PathBuf,
+    // Port to serve on if it is free; otherwise any free port is used
+    pub port: Option<u16>,
+    // Crashes survived before the server is given up on
+    pub max_restarts: usize,
+    // Lines of stdout/stderr kept for error reports
+    pub buffer_lines: usize,
+}
+
+impl DevServerConfig {
+    // `npm run dev` in `dir`, the way the TS client started Next.js and express apps
+    pub fn npm_run_dev(dir: PathBuf, port: Option<u16>) -> Self {
+        DevServerConfig {
+            program: "npm".to_string(),
+            args: vec!["run".to_string(), "dev".to_string()],
+            dir,
+            port,
+            max_restarts: 3,
+            buffer_lines: 500,
+        }
+    }
+}
+
+// A dev server running as its own process group. Dropping it kills the group.
+pub struct DevServer {
+    port: u16,
+    output: Arc<Mutex<VecDeque<String>>>,
+    lines: broadcast::Sender<String>,
+    // Process group of the running server, 0 between restarts
+    pgid: Arc<AtomicI32>,
+    stopping: Arc<AtomicBool>,
+    supervisor: JoinHandle<()>,
+}
+
+impl DevServer {
+    pub fn start(config: DevServerConfig) -> Result<DevServer> {
+        let port = pick_port(config.port)?;
+        let output = Arc::new(Mutex::new(VecDeque::with_capacity(config.buffer_lines)));
+        let (lines, _) = broadcast::channel(1024);
+        let pgid = Arc::new(AtomicI32::new(0));
+        let stopping = Arc::new(AtomicBool::new(false));
+
+        // Spawn the first process here so a missing program is reported right away
+        let output_sink = OutputSink { buffer: output.clone(), lines: lines.clone(), buffer_lines: config.buffer_lines };
+        let child = spawn(&config, port, &output_sink)?;
+        pgid.store(child.id().unwrap_or(0) as i32, Ordering::SeqCst);
+        let supervisor = tokio::spawn(supervise(config, port, child, output_sink, pgid.clone(), stopping.clone()));
+
+        Ok(DevServer { port, output, lines, pgid, stopping, supervisor })
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    // The most recent stdout/stderr lines, oldest first
+    #[allow(dead_code)]
+    pub fn output(&self) -> Vec<String> {
+        self.output.lock().unwrap().iter().cloned().collect()
+    }
+
+    // Watches output printed from now on; take one before writing a file
+    pub fn watch(&self) -> CompileWatch {
+        CompileWatch { lines: self.lines.subscribe() }
+    }
+
+    // Points a localhost URL at the port the server actually listens on
+    pub fn local_url(&self, url: &str) -> String {
+        let Ok(mut parsed) = url::Url::parse(url) else {
+            return url.to_string();
+        };
+        let is_local = matches!(parsed.host_str(), Some("localhost" | "127.0.0.1" | "[::1]"));
+        if is_local && parsed.port_or_known_default() != Some(self.port) && parsed.set_port(Some(self.port)).is_ok() {
+            return parsed.to_string();
+        }
+        url.to_string()
+    }
+}
+
+impl Drop for DevServer {
+    fn drop(&mut self) {
+        self.stopping.store(true, Ordering::SeqCst);
+        kill_group(self.pgid.swap(0, Ordering::SeqCst));
+        self.supervisor.abort();
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CompileEvent {
+    Compiled,
+    // The error report as printed by the dev server
+    Failed(String),
+}
+
+// Dev-server output since the watch was taken, scanned for compile results
+pub struct CompileWatch {
+    lines: broadcast::Receiver<String>,
+}
+
+impl CompileWatch {
+    // Scans the lines printed since the last check without waiting for more.
+    // An error wins over a success printed in the same batch.
+    pub async fn check(&mut self) -> Option<CompileEvent> {
+        let mut compiled = false;
+        loop {
+            match self.lines.try_recv() {
+                Ok(line) if compile_error().is_match(&line) => {
+                    return Some(CompileEvent::Failed(self.error_report(line).await));
+                }
+                Ok(line) => compiled |= compile_success().is_match(&line),
+                Err(TryRecvError::Lagged(_)) => continue,
+                Err(_) => break,
+            }
+        }
+        compiled.then_some(CompileEvent::Compiled)
+    }
+
+    // The first error line plus whatever follows it before the output goes quiet
+    async fn error_report(&mut self, first_line: String) -> String {
+        let mut report = vec![first_line];
+        while report.len() < MAX_ERROR_LINES {
+            match tokio::time::timeout(Duration::from_millis(300), self.lines.recv()).await {
+                Ok(Ok(line)) => report.push(line),
+                Ok(Err(RecvError::Lagged(_))) => continue,
+                _ => break,
+            }
+        }
+        report.join("\n")
+    }
+}
+
+// The port to serve on: the preferred one when it is free, otherwise one the OS hands out
+pub fn pick_port(preferred: Option<u16>) -> Result<u16> {
+    if let Some(port) = preferred {
+        if TcpListener::bind(("127.0.0.1", port)).is_ok() {
+            return Ok(port);
+        }
+        warn!("Port {} is in use, picking another one for the dev server", port);
+    }
+    let listener = TcpListener::bind(("127.0.0.1", 0)).context("Failed to find a free port")?;
+    Ok(listener.local_addr()?.port())
+}
+
+async fn supervise(config: DevServerConfig, port: u16, mut child: Child, output: OutputSink, pgid: Arc<AtomicI32>, stopping: Arc<AtomicBool>) {
+    let mut restarts = 0;
+    loop {
+        let status = child.wait().await;
+        // Whatever the crashed process left behind would hold on to the port
+        kill_group(pgid.swap(0, Ordering::SeqCst));
+        if stopping.load(Ordering::SeqCst) {
+            return;
+        }
+        let status = status.map(|status| status.to_string()).unwrap_or_else(|err| err.to_string());
+        if restarts >= config.max_restarts {
+            error!("Dev server exited ({}), giving up after {} restart(s)", status, restarts);
+            return;
+        }
+        restarts += 1;
+        warn!("Dev server exited ({}), restarting ({}/{})", status, restarts, config.max_restarts);
+        tokio::time::sleep(Duration::from_secs(1)).await;
+
+        child = match spawn(&config, port, &output) {
+            Ok(child) => child,
+            Err(err) => {
+                error!("Failed to restart the dev server: {:#}", err);
+                return;
+            }
+        };
+        pgid.store(child.id().unwrap_or(0) as i32, Ordering::SeqCst);
+    }
+}
+
+fn spawn(config: &DevServerConfig, port: u16, output: &OutputSink) -> Result<Child> {
+    let mut command = std::process::Command::new(&config.program);
+    command.args(&config.args)
+        .current_dir(&config.dir)
+        .env("PORT", port.to_string())
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        // Its own group, so npm's children die with it
+        .process_group(0);
+    let mut child = Command::from(command)
+        .kill_on_drop(true)
+        .spawn()
+        .with_context(|| format!("Failed to start `{} {}` in {}", config.program, config.args.join(" "), config.dir.display()))?;
+
+    if let Some(stdout) = child.stdout.take() {
+        tokio::spawn(collect_lines(stdout, output.clone()));
+    }
+    if let Some(stderr) = child.stderr.take() {
+        tokio::spawn(collect_lines(stderr, output.clone()));
+    }
+    Ok(child)
+}
+
+// Where the lines of every run of the server go
+#[derive(Clone)]
+struct OutputSink {
+    buffer: Arc<Mutex<VecDeque<String>>>,
+    lines: broadcast::Sender<String>,
+    buffer_lines: usize,
+}
+
+async fn collect_lines(stream: impl AsyncRead + Unpin, output: OutputSink) {
+    let mut lines = BufReader::new(stream).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        let line = ansi_escapes().replace_all(&line, "").into_owned();
+        debug!("dev server: {}", line);
+        {
+            let mut buffer = output.buffer.lock().unwrap();
+            if buffer.len() >= output.buffer_lines.max(1) {
+                buffer.pop_front();
+            }
+            buffer.push_back(line.clone());
+        }
+        // Nobody watching is fine
+        let _ = output.lines.send(line);
+    }
+}
+
+fn compile_success() -> &'static Regex {
+    static REGEX: OnceLock<Regex> = OnceLock::new();
+    REGEX.get_or_init(|| Regex::new(COMPILE_SUCCESS).unwrap())
+}
+
+fn compile_error() -> &'static Regex {
+    static REGEX: OnceLock<Regex> = OnceLock::new();
+    REGEX.get_or_init(|| Regex::new(COMPILE_ERROR).unwrap())
+}
+
+// Colour codes Next.js and nodemon put in their output
+fn ansi_escapes() -> &'static Regex {
+    static REGEX: OnceLock<Regex> = OnceLock::new();
+    REGEX.get_or_init(|| Regex::new(r"\x1b\[[0-9;?]*[A-Za-z]").unwrap())
+}
+
+pub fn kill_group(pgid: i32) {
+    if pgid > 0 {
+        // SAFETY: kill has no memory-safety preconditions; a negative pid targets the group
+        unsafe {
+            libc::kill(-pgid, libc::SIGTERM);
+        }
+    }
+}
@@ -0,0 +1,62 @@
+use regex::Regex;
+use scraper::{Html, Selector};
+use crate::library::feature::Expectations;
+use crate::library::log_and_run::RunReport;
+
+// Checks a test run against a step's expectations, returning one line per
+// expectation that wasn't met
+pub fn check_expectations(expect: &Expectations, report: &RunReport) -> Vec<String> {
+    let mut failures = Vec::new();
+
+    for pattern in &expect.logs {
+        match Regex::new(pattern) {
+            Ok(regex) if report.console.iter().any(|message| regex.is_match(&message.text)) => {}
+            Ok(_) => failures.push(format!("No console message matched /{}/", pattern)),
+            Err(err) => failures.push(format!("Invalid log pattern /{}/: {}", pattern, err)),
+        }
+    }
+    for pattern in &expect.forbidden_logs {
+        match Regex::new(pattern) {
+            Ok(regex) => {
+                if let Some(message) = report.console.iter().find(|message| regex.is_match(&message.text)) {
+                    failures.push(format!("Console message \"{}\" matched forbidden /{}/", message.text, pattern));
+                }
+            }
+            Err(err) => failures.push(format!("Invalid forbidden log pattern /{}/: {}", pattern, err)),
+        }
+    }
+
+    if expect.no_page_errors {
+        for error in &report.page_errors {
+            failures.push(format!("Page error: {}", error.message));
+        }
+    }
+
+    if !expect.selectors.is_empty() {
+        match &report.html {
+            Some(html) => {
+                let document = Html::parse_document(html);
+                for expectation in &expect.selectors {
+                    let selector = match Selector::parse(&expectation.selector) {
+                        Ok(selector) => selector,
+                        Err(err) => {
+                            failures.push(format!("Invalid selector \"{}\": {}", expectation.selector, err));
+                            continue;
+                        }
+                    };
+                    let mut elements = document.select(&selector).peekable();
+                    match &expectation.text {
+                        _ if elements.peek().is_none() => failures.push(format!("No element matched \"{}\"", expectation.selector)),
+                        Some(text) if !elements.any(|element| element.text().collect::<String>().contains(text.as_str())) => {
+                            failures.push(format!("No element matching \"{}\" contained \"{}\"", expectation.selector, text));
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            None => failures.push("The page HTML was not captured, so selectors could not be checked".to_string()),
+        }
+    }
+
+    failures
+}
@@ -1,25 +1,163 @@
-use serde_json::{json, Value};
-use anyhow::{Context, Result}; // Importing Result and Context from anyhow crate
+use serde_json::json;
+use anyhow::Result; // Importing Result from anyhow crate
+use oxc_allocator::Allocator;
+use oxc_ast::ast::Statement;
+use oxc_parser::Parser;
+use oxc_span::SourceType;
+use crate::library::service_client::ServiceClient;
+use crate::library::syntax::first_syntax_error;
 
-pub async fn extract_jsx(input_string: &str) -> Result<String> {
+// What extract_jsx returns, followed by the reply, when it finds no code
+pub const NO_CODE_FOUND: &str = "Error: there was no valid JSX found in the following text: ";
+
+// Pulls the code out of an LLM reply in-process. The longest fenced block
+// that parses wins. Without one it follows the TS extractJSX: starting from
+// each line in turn, the rest of the reply is parsed, and when the parse only
+// fails past its first line the trailing prose is cut off there.
+// `source_type` is the target file's, so TypeScript replies parse too.
+pub fn extract_jsx(input_string: &str, source_type: SourceType) -> String {
+    let fenced = fenced_blocks(input_string).into_iter()
+        .map(|block| block.trim().to_string())
+        .filter(|block| !block.is_empty() && is_code(block, source_type))
+        .max_by_key(String::len);
+    if let Some(code) = fenced {
+        return code;
+    }
+
+    let input_string = remove_triple_backtick_lines(input_string);
+    let mut line_index = 0;
+    for line in input_string.split('\n') {
+        let code = input_string[line_index..].trim();
+        line_index += line.len() + 1;
+        if code.is_empty() {
+            break;
+        }
+        let Some(error) = first_syntax_error(code, source_type) else {
+            if starts_with_label(code, source_type) {
+                continue;
+            }
+            return code.to_string();
+        };
+        let error_on_first_line = !code[..error.offset.min(code.len())].contains('\n');
+        if !error_on_first_line && !line.trim().is_empty() {
+            let truncated = remove_last_line(&code[..error.offset]).replace("```", "");
+            let truncated = truncated.trim();
+            if !truncated.is_empty() && is_code(truncated, source_type) {
+                return truncated.to_string();
+            }
+        }
+    }
+    format!("{}{}", NO_CODE_FOUND, input_string)
+}
+
+// Calls the express-autocode-api extractor instead of parsing in-process
+pub async fn extract_jsx_service(service: &ServiceClient, input_string: &str) -> Result<String> {
     let body = json!({ "inputString": input_string });
-  
-    let client = reqwest::Client::new();
-    let response = client
-        .post("http://localhost:4000/api/extract-jsx")
+
+    let response = service
+        .post("/api/extract-jsx")
         .json(&body)
         .send()
         .await?;
-    
+
     let status = response.status();
     let text = response.text().await?;
-  
+
     if status.is_success() {
         let json_response: serde_json::Value = serde_json::from_str(&text)?;
         if let Some(js_str) = json_response.get("JSX").and_then(|s| s.as_str()) {
             return Ok(js_str.to_string());
         }
     }
-  
+
     anyhow::bail!("Failed to extract JSX from API. Status code: {}", status)
-  }
\ No newline at end of file
+}
+
+// Whether `code` parses as code rather than prose
+fn is_code(code: &str, source_type: SourceType) -> bool {
+    first_syntax_error(code, source_type).is_none() && !starts_with_label(code, source_type)
+}
+
+// "Answer:" before the code parses as a statement label, but no component
+// file starts with one
+fn starts_with_label(code: &str, source_type: SourceType) -> bool {
+    let allocator = Allocator::default();
+    let parsed = Parser::new(&allocator, code, source_type).parse();
+    matches!(parsed.program.body.first(), Some(Statement::LabeledStatement(_)))
+}
+
+// The contents of each ``` fenced block, including one left unclosed
+fn fenced_blocks(input_string: &str) -> Vec<String> {
+    let mut blocks = Vec::new();
+    let mut current: Option<Vec<&str>> = None;
+    for line in input_string.lines() {
+        if line.trim_start().starts_with("```") {
+            match current.take() {
+                Some(lines) => blocks.push(lines.join("\n")),
+                None => current = Some(Vec::new()),
+            }
+        } else if let Some(lines) = current.as_mut() {
+            lines.push(line);
+        }
+    }
+    blocks.extend(current.map(|lines| lines.join("\n")));
+    blocks
+}
+
+fn remove_last_line(code: &str) -> &str {
+    match code.rfind('\n') {
+        Some(index) => &code[..index],
+        None => code,
+    }
+}
+
+fn remove_triple_backtick_lines(input_string: &str) -> String {
+    input_string.split('\n')
+        .filter(|line| !line.contains("```"))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::syntax::source_type_for;
+
+    const PAGE: &str = "export default function Page() {\n  return <p>Hello</p>;\n}";
+
+    fn extract(reply: &str) -> String {
+        extract_jsx(reply, source_type_for("page.jsx"))
+    }
+
+    #[test]
+    fn takes_the_fenced_block() {
+        assert_eq!(extract(&format!("```jsx\n{}\n```", PAGE)), PAGE);
+    }
+
+    #[test]
+    fn takes_the_fenced_block_out_of_prose() {
+        let reply = format!("Answer:\n```jsx\n{}\n```\nThis shows a greeting.\n\nRun it with:\n```bash\nnpm run dev\n```", PAGE);
+        assert_eq!(extract(&reply), PAGE);
+    }
+
+    #[test]
+    fn skips_a_prose_label_before_unfenced_code() {
+        assert_eq!(extract(&format!("Answer:\n{}", PAGE)), PAGE);
+    }
+
+    #[test]
+    fn cuts_off_trailing_prose() {
+        assert_eq!(extract(&format!("Here is the page:\n{}\n\nThis renders a greeting on the page.", PAGE)), PAGE);
+    }
+
+    #[test]
+    fn parses_tsx_replies_as_tsx() {
+        let code = "interface Props {\n  name: string;\n}\n\nexport default function Page({ name }: Props) {\n  return <p>{name}</p>;\n}";
+        assert_eq!(extract_jsx(&format!("```tsx\n{}\n```", code), source_type_for("page.tsx")), code);
+    }
+
+    #[test]
+    fn reports_replies_without_code() {
+        assert!(extract("I can't help with that.").starts_with(NO_CODE_FOUND));
+    }
+}
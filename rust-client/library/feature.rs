@@ -0,0 +1,293 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use anyhow::{Context, Result}; // Importing Result and Context from anyhow crate
+use std::fs;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Feature {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub doc_id: Option<String>,
+    #[serde(rename = "repoURL", default, skip_serializing_if = "Option::is_none")]
+    pub repo_url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dotenv_contents: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub autocode_dotenv: Option<String>,
+    #[serde(rename = "serviceJSON", default, skip_serializing_if = "Option::is_none")]
+    pub service_json: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub llm: Option<LlmConfig>,
+    // The project's own test suite, run after every write of every step
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub test_command: Option<String>,
+    // JUnit XML report the test command writes, relative to the repo
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub junit_report: Option<String>,
+    // Run after every write, before the page is loaded, e.g. "npx tsc --noEmit"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub typecheck_command: Option<String>,
+    // Likewise, e.g. "npx eslint {file}"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lint_command: Option<String>,
+    pub steps: Vec<Step>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Step {
+    pub description: String,
+    pub target: String,
+    // May leave out the target, in which case `target` is its path in the repo
+    #[serde(default)]
+    pub files: Vec<FileRef>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub test_path: Option<String>,
+    // Specs store this as "true"/"false" strings, so both spellings are accepted
+    #[serde(rename = "showHTML", default, with = "bool_like")]
+    pub show_html: bool,
+    // Overrides the feature's llm settings for this step only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub llm: Option<LlmConfig>,
+    // Overrides --max-attempts for this step
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_attempts: Option<usize>,
+    // Overrides --repair-strategy for this step
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repair_strategy: Option<RepairStrategy>,
+    // Overrides the feature's testCommand and junitReport for this step
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub test_command: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub junit_report: Option<String>,
+    // Override the feature's typecheckCommand and lintCommand for this step
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub typecheck_command: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lint_command: Option<String>,
+    // Checks on the test run that decide the step without asking the model
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expect: Option<Expectations>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Expectations {
+    // Regexes that some console message must match
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub logs: Vec<String>,
+    // Regexes that no console message may match
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub forbidden_logs: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub selectors: Vec<SelectorExpectation>,
+    #[serde(default)]
+    pub no_page_errors: bool,
+    // Also ask the model once every expectation is met
+    #[serde(default)]
+    pub evaluate: bool,
+}
+
+// A CSS selector that must match an element of the rendered page
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SelectorExpectation {
+    pub selector: String,
+    // Text that one of the matching elements must contain
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FileRef {
+    pub file_name: String,
+    pub file_path: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_contents: Option<String>,
+    #[serde(default)]
+    pub is_target: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ProviderKind {
+    #[serde(rename = "openai")]
+    #[value(name = "openai")]
+    OpenAi,
+    Anthropic,
+    Mock,
+}
+
+// How a step asks for another attempt after a failed one
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum RepairStrategy {
+    /// Keep one conversation, adding each attempt's results as new turns
+    Conversation,
+    /// Start over each time with a single prompt describing the last attempt
+    Fresh,
+    /// Use the evaluator's corrected code, continuing the conversation when it has none
+    Evaluator,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LlmConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provider: Option<ProviderKind>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+    // Tokens the model accepts in all, for models the client doesn't know
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context_window: Option<usize>,
+    #[serde(rename = "baseURL", default, skip_serializing_if = "Option::is_none")]
+    pub base_url: Option<String>,
+    // Name of the environment variable holding the API key
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_key_env: Option<String>,
+    // Scripted replies for the mock provider
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub responses: Option<Vec<String>>,
+}
+
+impl LlmConfig {
+    // Fields set in `overrides` win; a provider switch drops the connection settings of the old one
+    pub fn merged(&self, overrides: Option<&LlmConfig>) -> LlmConfig {
+        let Some(overrides) = overrides else {
+            return self.clone();
+        };
+        let base = if overrides.provider.is_some() && overrides.provider != self.provider {
+            LlmConfig { temperature: self.temperature, max_tokens: self.max_tokens, ..LlmConfig::default() }
+        } else {
+            self.clone()
+        };
+        LlmConfig {
+            provider: overrides.provider.or(base.provider),
+            model: overrides.model.clone().or(base.model),
+            temperature: overrides.temperature.or(base.temperature),
+            max_tokens: overrides.max_tokens.or(base.max_tokens),
+            context_window: overrides.context_window.or(base.context_window),
+            base_url: overrides.base_url.clone().or(base.base_url),
+            api_key_env: overrides.api_key_env.clone().or(base.api_key_env),
+            responses: overrides.responses.clone().or(base.responses),
+        }
+    }
+}
+
+impl Step {
+    pub fn target_file(&self) -> Option<&FileRef> {
+        self.files.iter().find(|file| file.is_target)
+    }
+
+    // Marks the listed file named `target` as the target when none is marked,
+    // or adds the target to `files` when the spec only named it in `target`
+    pub fn add_target_file(&mut self) {
+        if self.target_file().is_some() {
+            return;
+        }
+        if let Some(file) = self.files.iter_mut().find(|file| file.file_name == self.target) {
+            file.is_target = true;
+        } else {
+            self.files.insert(0, FileRef {
+                file_name: self.target.clone(),
+                file_path: self.target.clone(),
+                file_contents: None,
+                is_target: true,
+            });
+        }
+    }
+}
+
+impl Expectations {
+    // Selectors are checked against the page HTML, so it has to be captured
+    pub fn needs_html(&self) -> bool {
+        !self.selectors.is_empty()
+    }
+}
+
+pub fn load_feature(path: &str) -> Result<Feature> {
+    let feature_data_str = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read file: {}", path))?;
+    parse_feature(&feature_data_str)
+        .with_context(|| format!("Failed to deserialize JSON data from file: {}", path))
+}
+
+pub fn parse_feature(feature_data_str: &str) -> Result<Feature> {
+    let deserializer = &mut serde_json::Deserializer::from_str(feature_data_str);
+    serde_path_to_error::deserialize(deserializer).map_err(|err| {
+        let path = err.path().to_string();
+        anyhow::anyhow!("Invalid feature spec at `{}`: {}", path, err.into_inner())
+    })
+}
+
+pub fn feature_from_value(feature_data: serde_json::Value) -> Result<Feature> {
+    serde_path_to_error::deserialize(feature_data).map_err(|err| {
+        let path = err.path().to_string();
+        anyhow::anyhow!("Invalid feature spec at `{}`: {}", path, err.into_inner())
+    })
+}
+
+mod bool_like {
+    use super::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(value: &bool, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(if *value { "true" } else { "false" })
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<bool, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum BoolLike {
+            Bool(bool),
+            Str(String),
+        }
+        match BoolLike::deserialize(deserializer)? {
+            BoolLike::Bool(value) => Ok(value),
+            BoolLike::Str(value) => match value.to_lowercase().as_str() {
+                "true" => Ok(true),
+                "false" => Ok(false),
+                _ => Err(serde::de::Error::custom(format!("expected \"true\" or \"false\", found \"{}\"", value))),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn step(target: &str, files: Vec<FileRef>) -> Step {
+        serde_json::from_value(serde_json::json!({ "description": "d", "target": target, "files": files })).unwrap()
+    }
+
+    fn file(file_name: &str, file_path: &str, is_target: bool) -> FileRef {
+        FileRef { file_name: file_name.to_string(), file_path: file_path.to_string(), file_contents: None, is_target }
+    }
+
+    #[test]
+    fn listed_file_named_by_target_becomes_the_target() {
+        let mut step = step("page.js", vec![file("layout.js", "app/layout.js", false), file("page.js", "app/page.js", false)]);
+        step.add_target_file();
+        assert_eq!(step.files.len(), 2);
+        assert_eq!(step.target_file().map(|file| file.file_path.as_str()), Some("app/page.js"));
+    }
+
+    #[test]
+    fn target_is_added_when_not_listed() {
+        let mut unlisted = step("app/page.js", vec![]);
+        unlisted.add_target_file();
+        assert_eq!(unlisted.target_file().map(|file| file.file_path.as_str()), Some("app/page.js"));
+
+        let mut marked = step("page.js", vec![file("page.js", "app/page.js", false), file("main.js", "main.js", true)]);
+        marked.add_target_file();
+        assert_eq!(marked.target_file().map(|file| file.file_path.as_str()), Some("main.js"));
+    }
+}
@@ -1,18 +1,16 @@
-use serde_json::{json, Value};
 use anyhow::{Context, Result};
+use serde_json::Value;
+use crate::library::service_client::ServiceClient;
 
-pub async fn get_feature() -> Result<Value> {
-    let url = "http://localhost:4000/api/get-feature";
-  
-    let client = reqwest::Client::new();
-    let response = client
-        .get(url)
+pub async fn get_feature(service: &ServiceClient) -> Result<Value> {
+    let response = service
+        .get("/api/get-feature")
         .send()
         .await?;
     
     let status = response.status();
     let feature_text = response.text().await?;
-    println!("Logs: {}", feature_text);
+    log::debug!("Logs: {}", feature_text);
   
     if status.is_success() {
         let feature_json: Value = serde_json::from_str(&feature_text)
@@ -1,25 +1,431 @@
-use serde_json::{json, Value};
+use serde_json::json;
 use anyhow::{Context, Result}; // Importing Result and Context from anyhow crate
+use log::warn;
+use oxc_allocator::Allocator;
+use oxc_ast::ast::{Declaration, ExportDefaultDeclarationKind, Expression, ImportDeclarationSpecifier, Statement, TSModuleDeclarationName, VariableDeclaration};
+use oxc_parser::Parser;
+use oxc_span::{GetSpan, SourceType, Span};
+use std::ops::Range;
+use crate::library::service_client::ServiceClient;
 
-pub async fn get_updated_functions(existing_contents: &str, new_contents:&str) -> Result<String> {
+// A top-level function, arrow-function const, class, TypeScript declaration or
+// default export
+#[derive(Debug)]
+struct Definition {
+    // None for anonymous default exports
+    name: Option<String>,
+    // Interfaces and type aliases, which can share a name with a value
+    type_only: bool,
+    // The whole statement, including any `export` or `export default`
+    outer: Range<usize>,
+    // The declaration without its export wrapper
+    inner: Range<usize>,
+    exported: bool,
+    default_export: bool,
+}
+
+#[derive(Debug)]
+struct Import {
+    span: Range<usize>,
+    source: String,
+    // `import type { ... }`, kept apart from value imports of the same module
+    type_only: bool,
+    // The source literal as written, quotes included
+    source_text: String,
+    default: Option<String>,
+    namespace: Option<String>,
+    // (imported, local, specifier text)
+    named: Vec<(String, String, String)>,
+    default_end: Option<usize>,
+    last_named_end: Option<usize>,
+}
+
+#[derive(Debug)]
+struct Outline {
+    directives_end: usize,
+    imports: Vec<Import>,
+    definitions: Vec<Definition>,
+    // `export default Home;` statements, with the name they export
+    default_reexports: Vec<(Range<usize>, String)>,
+    // Top-level statements that are none of the above, which aren't spliced
+    others: Vec<Range<usize>>,
+}
+
+struct Edit {
+    range: Range<usize>,
+    text: String,
+}
+
+// Splices the functions in `new_contents` into `existing_contents`: definitions
+// with a matching name are replaced, new ones are appended and new imports are
+// merged in. A new default export takes the place of the old one, including
+// an `export default Name;` line. Everything else in the existing file is kept
+// byte-for-byte. `source_type` is the target file's, see syntax::source_type_for.
+pub fn get_updated_functions(existing_contents: &str, new_contents: &str, source_type: SourceType) -> Result<String> {
+    let existing = outline(existing_contents, source_type).context("Failed to parse the existing file")?;
+    let new = outline(new_contents, source_type).context("Failed to parse the new code")?;
+    for other in &new.others {
+        warn!("Left out of the splice, since only imports and definitions are spliced: {}", first_line(&new_contents[other.clone()]));
+    }
+
+    let mut edits = Vec::new();
+    let mut appended = Vec::new();
+    for definition in &new.definitions {
+        let matching = existing.definitions.iter()
+            .find(|existing_definition| definition.name.is_some() && existing_definition.name == definition.name && existing_definition.type_only == definition.type_only)
+            .or_else(|| if definition.default_export {
+                existing.definitions.iter().find(|existing_definition| existing_definition.default_export)
+            } else {
+                None
+            });
+        match matching {
+            // Keep the existing export wrapper when the snippet left it off
+            Some(existing_definition) if existing_definition.exported && !definition.exported => {
+                edits.push(Edit { range: existing_definition.inner.clone(), text: new_contents[definition.inner.clone()].to_string() });
+            }
+            Some(existing_definition) => {
+                edits.push(Edit { range: existing_definition.outer.clone(), text: new_contents[definition.outer.clone()].to_string() });
+            }
+            None => appended.push(new_contents[definition.outer.clone()].to_string()),
+        }
+    }
+    edits.retain(|edit| existing_contents[edit.range.clone()] != edit.text);
+
+    // A module can only have one default export
+    if new.definitions.iter().any(|definition| definition.default_export) {
+        for (span, _) in &existing.default_reexports {
+            edits.push(Edit { range: with_line_break(existing_contents, span.clone()), text: String::new() });
+        }
+    }
+    let has_default_export = existing.definitions.iter().any(|definition| definition.default_export) || !existing.default_reexports.is_empty();
+    for (span, name) in &new.default_reexports {
+        if has_default_export {
+            continue;
+        }
+        if existing.definitions.iter().chain(&new.definitions).any(|definition| definition.name.as_ref() == Some(name)) {
+            appended.push(new_contents[span.clone()].to_string());
+        } else {
+            warn!("Left out of the splice, since {} isn't defined: {}", name, first_line(&new_contents[span.clone()]));
+        }
+    }
+
+    let (import_edits, added_imports) = merge_imports(&existing, &new);
+    edits.extend(import_edits);
+    if !added_imports.is_empty() {
+        let added_imports = added_imports.join("\n");
+        let edit = match (existing.imports.last(), existing.directives_end) {
+            (Some(last_import), _) => Edit { range: last_import.span.end..last_import.span.end, text: format!("\n{}", added_imports) },
+            (None, 0) => Edit { range: 0..0, text: format!("{}\n", added_imports) },
+            (None, directives_end) => Edit { range: directives_end..directives_end, text: format!("\n{}", added_imports) },
+        };
+        edits.push(edit);
+    }
+    if !appended.is_empty() {
+        let separator = if existing_contents.ends_with('\n') { "\n" } else { "\n\n" };
+        let end = existing_contents.len();
+        edits.push(Edit { range: end..end, text: format!("{}{}\n", separator, appended.join("\n\n")) });
+    }
+
+    // Inserted code follows the existing file's line endings
+    if existing_contents.contains("\r\n") {
+        for edit in &mut edits {
+            edit.text = edit.text.replace("\r\n", "\n").replace('\n', "\r\n");
+        }
+    }
+    Ok(apply_edits(existing_contents, edits))
+}
+
+// Calls the express-autocode-api splicer instead of parsing in-process
+pub async fn get_updated_functions_service(service: &ServiceClient, existing_contents: &str, new_contents: &str) -> Result<String> {
     let body = json!({ "existingContents": existing_contents, "newContents": new_contents });
-  
-    let client = reqwest::Client::new();
-    let response = client
-        .post("http://localhost:4000/api/get-updated-functions")
+
+    let response = service
+        .post("/api/get-updated-functions")
         .json(&body)
         .send()
         .await?;
-    
+
     let status = response.status();
     let text = response.text().await?;
-  
+
     if status.is_success() {
         let json_response: serde_json::Value = serde_json::from_str(&text)?;
         if let Some(js_str) = json_response.get("JSX").and_then(|s| s.as_str()) {
             return Ok(js_str.to_string());
         }
     }
-  
+
     anyhow::bail!("Failed to get updated functions using API. Status code: {}", status)
-  }
\ No newline at end of file
+}
+
+// Returns edits to existing imports, and whole import statements to add
+fn merge_imports(existing: &Outline, new: &Outline) -> (Vec<Edit>, Vec<String>) {
+    let mut edits = Vec::new();
+    let mut added = Vec::new();
+    for import in &new.imports {
+        let Some(existing_import) = existing.imports.iter().find(|existing_import| existing_import.source == import.source && existing_import.type_only == import.type_only) else {
+            added.push(import_text(import));
+            continue;
+        };
+
+        let missing_named: Vec<&str> = import.named.iter()
+            .filter(|(imported, local, _)| !existing_import.named.iter().any(|(i, l, _)| i == imported && l == local))
+            .map(|(_, _, text)| text.as_str())
+            .collect();
+        if !missing_named.is_empty() {
+            if let Some(end) = existing_import.last_named_end {
+                edits.push(Edit { range: end..end, text: format!(", {}", missing_named.join(", ")) });
+            } else if let (Some(end), None) = (existing_import.default_end, &existing_import.namespace) {
+                edits.push(Edit { range: end..end, text: format!(", {{ {} }}", missing_named.join(", ")) });
+            } else {
+                added.push(format!("{} {{ {} }} from {};", import_keyword(import), missing_named.join(", "), import.source_text));
+            }
+        }
+        if let Some(default) = &import.default {
+            if existing_import.default.as_ref() != Some(default) {
+                added.push(format!("{} {} from {};", import_keyword(import), default, import.source_text));
+            }
+        }
+        if let Some(namespace) = &import.namespace {
+            if existing_import.namespace.as_ref() != Some(namespace) {
+                added.push(format!("import * as {} from {};", namespace, import.source_text));
+            }
+        }
+    }
+    (edits, added)
+}
+
+fn import_text(import: &Import) -> String {
+    let mut parts = Vec::new();
+    if let Some(default) = &import.default {
+        parts.push(default.clone());
+    }
+    if let Some(namespace) = &import.namespace {
+        parts.push(format!("* as {}", namespace));
+    }
+    if !import.named.is_empty() {
+        let named: Vec<&str> = import.named.iter().map(|(_, _, text)| text.as_str()).collect();
+        parts.push(format!("{{ {} }}", named.join(", ")));
+    }
+    if parts.is_empty() {
+        format!("import {};", import.source_text)
+    } else {
+        format!("{} {} from {};", import_keyword(import), parts.join(", "), import.source_text)
+    }
+}
+
+fn import_keyword(import: &Import) -> &'static str {
+    if import.type_only { "import type" } else { "import" }
+}
+
+// Applies edits back to front, skipping any that overlap an earlier one
+fn apply_edits(contents: &str, mut edits: Vec<Edit>) -> String {
+    edits.sort_by_key(|edit| (edit.range.start, edit.range.end));
+    let mut kept: Vec<Edit> = Vec::new();
+    for edit in edits {
+        if let Some(previous) = kept.last() {
+            if edit.range.start < previous.range.end {
+                warn!("Skipped a splice edit that overlaps another, so this is kept as it was: {}", first_line(&contents[edit.range.clone()]));
+                continue;
+            }
+        }
+        kept.push(edit);
+    }
+    let mut updated = contents.to_string();
+    for edit in kept.iter().rev() {
+        updated.replace_range(edit.range.clone(), &edit.text);
+    }
+    updated
+}
+
+fn outline(code: &str, source_type: SourceType) -> Result<Outline> {
+    let allocator = Allocator::default();
+    let parsed = Parser::new(&allocator, code, source_type).parse();
+    if let Some(error) = parsed.errors.first() {
+        anyhow::bail!("{}", error.message);
+    }
+    let program = parsed.program;
+
+    let mut outline = Outline {
+        directives_end: program.directives.last().map(|directive| directive.span.end as usize).unwrap_or(0),
+        imports: Vec::new(),
+        definitions: Vec::new(),
+        default_reexports: Vec::new(),
+        others: Vec::new(),
+    };
+    for statement in &program.body {
+        let outer = range(statement.span());
+        match statement {
+            Statement::ImportDeclaration(import) => {
+                let mut info = Import {
+                    span: range(import.span),
+                    source: import.source.value.to_string(),
+                    type_only: import.import_kind.is_type(),
+                    source_text: import.source.span.source_text(code).to_string(),
+                    default: None,
+                    namespace: None,
+                    named: Vec::new(),
+                    default_end: None,
+                    last_named_end: None,
+                };
+                for specifier in import.specifiers.iter().flatten() {
+                    match specifier {
+                        ImportDeclarationSpecifier::ImportSpecifier(named) => {
+                            info.named.push((named.imported.name().to_string(), named.local.name.to_string(), named.span.source_text(code).to_string()));
+                            info.last_named_end = Some(named.span.end as usize);
+                        }
+                        ImportDeclarationSpecifier::ImportDefaultSpecifier(default) => {
+                            info.default = Some(default.local.name.to_string());
+                            info.default_end = Some(default.span.end as usize);
+                        }
+                        ImportDeclarationSpecifier::ImportNamespaceSpecifier(namespace) => {
+                            info.namespace = Some(namespace.local.name.to_string());
+                        }
+                    }
+                }
+                outline.imports.push(info);
+            }
+            Statement::ExportNamedDeclaration(export) => {
+                let Some(declaration) = &export.declaration else {
+                    outline.others.push(outer);
+                    continue;
+                };
+                let inner = range(declaration.span());
+                match declaration_name(declaration) {
+                    Some((name, type_only)) => outline.definitions.push(definition(Some(name), type_only, outer, inner, true, false)),
+                    None => outline.others.push(outer),
+                }
+            }
+            Statement::ExportDefaultDeclaration(export) => {
+                let name = match &export.declaration {
+                    ExportDefaultDeclarationKind::FunctionDeclaration(function) => function.id.as_ref().map(|id| id.name.to_string()),
+                    ExportDefaultDeclarationKind::ClassDeclaration(class) => class.id.as_ref().map(|id| id.name.to_string()),
+                    // `export default Home;` only re-exports a definition found elsewhere
+                    ExportDefaultDeclarationKind::Identifier(id) => {
+                        outline.default_reexports.push((outer, id.name.to_string()));
+                        continue;
+                    }
+                    _ => None,
+                };
+                let inner = range(export.declaration.span());
+                outline.definitions.push(definition(name, false, outer, inner, true, true));
+            }
+            _ => match statement.as_declaration().and_then(declaration_name) {
+                Some((name, type_only)) => outline.definitions.push(definition(Some(name), type_only, outer.clone(), outer, false, false)),
+                None => outline.others.push(outer),
+            },
+        }
+    }
+    Ok(outline)
+}
+
+// The name a declaration is matched by, and whether it only declares a type.
+// Variables count only when they hold a function.
+fn declaration_name(declaration: &Declaration) -> Option<(String, bool)> {
+    match declaration {
+        Declaration::FunctionDeclaration(function) => function.id.as_ref().map(|id| (id.name.to_string(), false)),
+        Declaration::ClassDeclaration(class) => class.id.as_ref().map(|id| (id.name.to_string(), false)),
+        Declaration::VariableDeclaration(declaration) => function_const_name(declaration).map(|name| (name, false)),
+        Declaration::TSInterfaceDeclaration(interface) => Some((interface.id.name.to_string(), true)),
+        Declaration::TSTypeAliasDeclaration(alias) => Some((alias.id.name.to_string(), true)),
+        Declaration::TSEnumDeclaration(declaration) => Some((declaration.id.name.to_string(), false)),
+        Declaration::TSModuleDeclaration(module) => match &module.id {
+            TSModuleDeclarationName::Identifier(id) => Some((id.name.to_string(), false)),
+            TSModuleDeclarationName::StringLiteral(_) => None,
+        },
+        _ => None,
+    }
+}
+
+// Name of a single-declarator `const x = () => {}` or `const x = function () {}`
+fn function_const_name(declaration: &VariableDeclaration) -> Option<String> {
+    let [declarator] = declaration.declarations.as_slice() else {
+        return None;
+    };
+    match &declarator.init {
+        Some(Expression::ArrowFunctionExpression(_) | Expression::FunctionExpression(_)) => {
+            declarator.id.get_identifier_name().map(|name| name.to_string())
+        }
+        _ => None,
+    }
+}
+
+fn definition(name: Option<String>, type_only: bool, outer: Range<usize>, inner: Range<usize>, exported: bool, default_export: bool) -> Definition {
+    Definition { name, type_only, outer, inner, exported, default_export }
+}
+
+fn range(span: Span) -> Range<usize> {
+    span.start as usize..span.end as usize
+}
+
+// The range and the line break after it, for removing a whole statement
+fn with_line_break(code: &str, range: Range<usize>) -> Range<usize> {
+    let rest = &code[range.end..];
+    let end = range.end + if rest.starts_with("\r\n") { 2 } else if rest.starts_with('\n') { 1 } else { 0 };
+    range.start..end
+}
+
+fn first_line(code: &str) -> &str {
+    code.lines().next().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::syntax::source_type_for;
+
+    fn splice(existing: &str, new: &str) -> String {
+        get_updated_functions(existing, new, source_type_for("page.jsx")).unwrap()
+    }
+
+    #[test]
+    fn replaces_a_definition_and_keeps_the_rest_byte_for_byte() {
+        let existing = "'use client';\n\n// Counts clicks\nfunction count(n) {\n  return n;\n}\n\nconst  spaced =  1 ;\n\nexport function Page() {\n  return <p>{count(1)}</p>;\n}\n";
+        let updated = splice(existing, "function count(n) {\n  return n + 1;\n}");
+        assert_eq!(updated, existing.replace("return n;", "return n + 1;"));
+    }
+
+    #[test]
+    fn keeps_the_export_when_the_reply_leaves_it_off() {
+        let existing = "export function Page() {\n  return null;\n}\n";
+        let updated = splice(existing, "function Page() {\n  return <p />;\n}");
+        assert_eq!(updated, "export function Page() {\n  return <p />;\n}\n");
+    }
+
+    #[test]
+    fn appends_new_definitions() {
+        let existing = "export function Page() {\n  return null;\n}\n";
+        let updated = splice(existing, "const helper = () => 1;");
+        assert_eq!(updated, "export function Page() {\n  return null;\n}\n\nconst helper = () => 1;\n");
+    }
+
+    #[test]
+    fn default_export_replaces_a_separate_default_reexport() {
+        let existing = "function Home() {\n  return null;\n}\n\nexport default Home;\n";
+        let updated = splice(existing, "export default function Home() {\n  return <main />;\n}");
+        assert_eq!(updated, "export default function Home() {\n  return <main />;\n}\n\n");
+    }
+
+    #[test]
+    fn default_export_replaces_the_old_default_export() {
+        let existing = "export default function Home() {\n  return null;\n}\n";
+        let updated = splice(existing, "export default function Page() {\n  return <main />;\n}");
+        assert_eq!(updated, "export default function Page() {\n  return <main />;\n}\n");
+    }
+
+    #[test]
+    fn merges_imports() {
+        let existing = "import { useState } from 'react';\nimport type { Props } from './types';\n\nexport function Page() {\n  return null;\n}\n";
+        let new = "import { useState, useEffect } from 'react';\nimport type { Item } from './types';\nimport Link from 'next/link';\n\nexport function Page() {\n  useEffect(() => {});\n  return <Link href=\"/\" />;\n}";
+        let updated = get_updated_functions(existing, new, source_type_for("page.tsx")).unwrap();
+        assert!(updated.starts_with("import { useState, useEffect } from 'react';\nimport type { Props, Item } from './types';\nimport Link from 'next/link';\n"), "{}", updated);
+        assert!(updated.contains("useEffect(() => {});"));
+    }
+
+    #[test]
+    fn keeps_crlf_line_endings() {
+        let existing = "function a() {\r\n  return 1;\r\n}\r\n\r\nfunction b() {\r\n  return 2;\r\n}\r\n";
+        let updated = splice(existing, "function b() {\n  return 3;\n}\n\nfunction c() {\n  return 4;\n}");
+        assert_eq!(updated, "function a() {\r\n  return 1;\r\n}\r\n\r\nfunction b() {\r\n  return 3;\r\n}\r\n\r\nfunction c() {\r\n  return 4;\r\n}\r\n");
+    }
+}
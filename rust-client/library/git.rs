@@ -0,0 +1,108 @@
+use anyhow::{Context, Result}; // Importing Result and Context from anyhow crate
+use std::path::Path;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Used when the target repo has no identity of its own configured
+const FALLBACK_NAME: &str = "Autocode";
+const FALLBACK_EMAIL: &str = "autocode@localhost";
+
+pub fn is_git_repo(repo_dir: &Path) -> bool {
+    git(repo_dir, &["rev-parse", "--is-inside-work-tree"]).is_ok_and(|output| output == "true")
+}
+
+// Changes to tracked files, which a rollback would throw away. Untracked files
+// such as a freshly written .env are left alone by rollbacks, so they don't count.
+pub fn has_uncommitted_changes(repo_dir: &Path) -> Result<bool> {
+    Ok(!git(repo_dir, &["status", "--porcelain", "--untracked-files=no"])?.is_empty())
+}
+
+// A new branch name for a feature run, e.g. autocode/add-a-dark-mode-toggle-1718000000
+pub fn feature_branch_name(description: &str) -> String {
+    let mut slug = String::new();
+    for c in description.chars() {
+        if c.is_ascii_alphanumeric() {
+            slug.push(c.to_ascii_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+        if slug.len() >= 40 {
+            break;
+        }
+    }
+    let slug = slug.trim_end_matches('-');
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(0);
+    if slug.is_empty() {
+        format!("autocode/feature-{}", timestamp)
+    } else {
+        format!("autocode/{}-{}", slug, timestamp)
+    }
+}
+
+pub fn create_branch(repo_dir: &Path, branch: &str) -> Result<()> {
+    git(repo_dir, &["checkout", "-b", branch])
+        .with_context(|| format!("Failed to create branch {}", branch))?;
+    Ok(())
+}
+
+pub fn head_commit(repo_dir: &Path) -> Result<String> {
+    git(repo_dir, &["rev-parse", "HEAD"]).context("Failed to read the HEAD commit")
+}
+
+// Commits the given files, returning the new commit or None if nothing changed
+pub fn commit_files(repo_dir: &Path, paths: &[&str], message: &str) -> Result<Option<String>> {
+    let mut add = vec!["add", "--"];
+    add.extend_from_slice(paths);
+    git(repo_dir, &add)?;
+    if git(repo_dir, &["diff", "--cached", "--quiet"]).is_ok() {
+        return Ok(None);
+    }
+
+    let mut commit = Vec::new();
+    if git(repo_dir, &["config", "user.name"]).is_err() {
+        commit.extend(["-c".to_string(), format!("user.name={}", FALLBACK_NAME)]);
+    }
+    if git(repo_dir, &["config", "user.email"]).is_err() {
+        commit.extend(["-c".to_string(), format!("user.email={}", FALLBACK_EMAIL)]);
+    }
+    commit.extend(["commit".to_string(), "-m".to_string(), message.to_string()]);
+    let commit: Vec<&str> = commit.iter().map(String::as_str).collect();
+    git(repo_dir, &commit).context("Failed to commit the step")?;
+    head_commit(repo_dir).map(Some)
+}
+
+// Puts tracked files back as they were at `commit`, and removes any of the
+// given files that the failed step created
+pub fn reset_to(repo_dir: &Path, commit: &str, paths: &[&str]) -> Result<()> {
+    git(repo_dir, &["reset", "--hard", commit])
+        .with_context(|| format!("Failed to reset to {}", commit))?;
+    let mut clean = vec!["clean", "-f", "--"];
+    clean.extend_from_slice(paths);
+    git(repo_dir, &clean)?;
+    Ok(())
+}
+
+// The commits made since `base`, with the files they touched
+pub fn summarize_range(repo_dir: &Path, base: &str) -> Result<String> {
+    let range = format!("{}..HEAD", base);
+    let log = git(repo_dir, &["log", "--reverse", "--oneline", &range])?;
+    if log.is_empty() {
+        return Ok("No commits were made.".to_string());
+    }
+    let stat = git(repo_dir, &["diff", "--stat", &range])?;
+    let short_base = git(repo_dir, &["rev-parse", "--short", base])?;
+    Ok(format!("Commits {}..HEAD:\n{}\n\n{}", short_base, log, stat))
+}
+
+// Runs git in `repo_dir`, returning its trimmed stdout or failing with its stderr
+fn git(repo_dir: &Path, args: &[&str]) -> Result<String> {
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(repo_dir)
+        .output()
+        .with_context(|| "Failed to execute git")?;
+    if !output.status.success() {
+        anyhow::bail!("git {} failed: {}", args.join(" "), String::from_utf8_lossy(&output.stderr).trim());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
@@ -1,21 +1,311 @@
-use serde_json::{json, Value};
-use anyhow::{Context, Result};
-
-pub async fn log_and_run(test_path: &str, show_html: &str) -> Result<String> {
-    let url = format!("http://localhost:4000/api/log-and-run?testPath={}&showHTML={}", test_path, show_html);
-  
-    let client = reqwest::Client::new();
-    let response = client
-        .get(&url)
+use anyhow::{Context, Result}; // Importing Result and Context from anyhow crate
+use chromiumoxide::browser::{Browser, BrowserConfig};
+use chromiumoxide::cdp::browser_protocol::network::{EventLoadingFailed, EventRequestWillBeSent, EventResponseReceived};
+use chromiumoxide::cdp::js_protocol::runtime::{EventConsoleApiCalled, EventExceptionThrown, RemoteObject};
+use chromiumoxide::listeners::EventStream;
+use futures::{FutureExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
+use crate::library::service_client::ServiceClient;
+
+// Console noise from React and Next.js dev builds that says nothing about the change
+const IGNORED_MESSAGES: [&str; 6] = [
+    "Warning: A future version of React will block javascript: URLs as a security precaution.",
+    "Download the React DevTools for a better development experience",
+    "The value \"product-width\" for key \"width\" is invalid, and has been ignored.",
+    "is found, but is not used because the request credentials mode does not match. Consider taking a look at crossorigin attribute.",
+    "was preloaded using link preload but not used within a few seconds from the window's load event.",
+    "Failed to load resource: the server responded with a status of 500 (Internal Server Error)",
+];
+
+// Separates the page HTML from the console output in plain-text logs
+const HTML_HEADER: &str = "\nLog of current page HTML content:\n";
+
+#[derive(Debug, Clone)]
+pub struct BrowserOptions {
+    pub timeout: Duration,
+    pub chrome_path: Option<PathBuf>,
+    // Pages loaded one after another in the same browser, so tests of eg.
+    // localStorage see what the first load stored. See Autocode Design Decisions.
+    pub test_runs: usize,
+    // Time given to effects and timers after the load event
+    pub settle_time: Duration,
+}
+
+impl Default for BrowserOptions {
+    fn default() -> Self {
+        BrowserOptions {
+            timeout: Duration::from_secs(60),
+            chrome_path: None,
+            test_runs: 2,
+            settle_time: Duration::from_secs(3),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ConsoleMessage {
+    pub level: String,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PageError {
+    pub message: String,
+    pub stack: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct FailedRequest {
+    pub method: String,
+    pub url: String,
+    pub reason: String,
+}
+
+// What the last test run of a page produced
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RunReport {
+    pub console: Vec<ConsoleMessage>,
+    pub page_errors: Vec<PageError>,
+    pub failed_requests: Vec<FailedRequest>,
+    pub html: Option<String>,
+}
+
+impl RunReport {
+    // Reads the plain-text logs of the express-autocode-api runner, which
+    // doesn't tell console messages from page errors
+    pub fn from_logs(logs: &str) -> RunReport {
+        let (logs, html) = match logs.split_once(HTML_HEADER) {
+            Some((logs, html)) => (logs, Some(html.to_string())),
+            None => (logs, None),
+        };
+        RunReport {
+            console: logs.lines()
+                .filter(|line| !line.trim().is_empty())
+                .map(|line| ConsoleMessage { level: "log".to_string(), text: line.to_string() })
+                .collect(),
+            html,
+            ..RunReport::default()
+        }
+    }
+
+    // Renders the report in the plain-text shape the evaluation prompts expect
+    pub fn to_logs(&self) -> String {
+        let mut logs = String::new();
+        for message in &self.console {
+            if message.level == "log" {
+                logs += &format!("{}\n", message.text);
+            } else {
+                logs += &format!("[{}] {}\n", message.level, message.text);
+            }
+        }
+        for error in &self.page_errors {
+            logs += &format!("{}\n", error.stack.as_deref().unwrap_or(&error.message));
+        }
+        for request in &self.failed_requests {
+            logs += &format!("Failed request: {} {} ({})\n", request.method, request.url, request.reason);
+        }
+        if let Some(html) = &self.html {
+            logs += &format!("{}{}", HTML_HEADER, html);
+        }
+        logs
+    }
+}
+
+// Loads the test page in a local headless Chromium over the DevTools protocol
+pub async fn log_and_run(test_path: &str, show_html: bool, options: &BrowserOptions) -> Result<RunReport> {
+    let mut config = BrowserConfig::builder()
+        .new_headless_mode()
+        .request_timeout(options.timeout);
+    if let Some(chrome_path) = &options.chrome_path {
+        config = config.chrome_executable(chrome_path);
+    }
+    let config = config.build().map_err(|err| anyhow::anyhow!("Invalid browser config: {}", err))?;
+    let (mut browser, mut handler) = Browser::launch(config).await
+        .context("Failed to launch Chromium")?;
+    let handler_task = tokio::spawn(async move {
+        while handler.next().await.is_some() {}
+    });
+
+    let mut result = Ok(RunReport::default());
+    for _ in 0..options.test_runs.max(1) {
+        result = tokio::time::timeout(options.timeout, run_page(&browser, test_path, show_html, options.settle_time))
+            .await
+            .unwrap_or_else(|_| Err(anyhow::anyhow!("Evaluation timed out")));
+        if result.is_err() {
+            break;
+        }
+    }
+
+    if browser.close().await.is_err() {
+        browser.kill().await;
+    }
+    let _ = browser.wait().await;
+    handler_task.abort();
+    result
+}
+
+// Calls the express-autocode-api runner, which drives Puppeteer, instead of Chromium directly
+pub async fn log_and_run_service(service: &ServiceClient, test_path: &str, show_html: &str) -> Result<String> {
+    let response = service
+        .get("/api/log-and-run")
+        .query(&[("testPath", test_path), ("showHTML", show_html)])
         .send()
         .await?;
-    
+
     let status = response.status();
     let logs = response.text().await?;
-  
+
     if status.is_success() {
         return Ok(logs);
     }
-  
+
     anyhow::bail!("Failed to log and run. Status code: {}", status)
 }
+
+async fn run_page(browser: &Browser, test_path: &str, show_html: bool, settle_time: Duration) -> Result<RunReport> {
+    let page = browser.new_page("about:blank").await?;
+    let mut console_events = page.event_listener::<EventConsoleApiCalled>().await?;
+    let mut exception_events = page.event_listener::<EventExceptionThrown>().await?;
+    let mut request_events = page.event_listener::<EventRequestWillBeSent>().await?;
+    let mut response_events = page.event_listener::<EventResponseReceived>().await?;
+    let mut failure_events = page.event_listener::<EventLoadingFailed>().await?;
+
+    page.goto(test_path).await
+        .with_context(|| format!("Failed to load {}", test_path))?;
+    tokio::time::sleep(settle_time).await;
+
+    let mut report = RunReport::default();
+    for event in drain(&mut console_events) {
+        let text = event.args.iter().map(remote_object_text).collect::<Vec<_>>().join(" ");
+        if !IGNORED_MESSAGES.iter().any(|ignored| text.contains(ignored)) {
+            report.console.push(ConsoleMessage { level: event.r#type.as_ref().to_string(), text });
+        }
+    }
+    for event in drain(&mut exception_events) {
+        let details = &event.exception_details;
+        let description = details.exception.as_ref().and_then(|exception| exception.description.clone());
+        let message = description.as_deref()
+            .and_then(|description| description.lines().next())
+            .unwrap_or(&details.text)
+            .to_string();
+        let stack = description.or_else(|| details.stack_trace.as_ref().map(|stack_trace| {
+            let frames: Vec<String> = stack_trace.call_frames.iter()
+                .map(|frame| format!("    at {} ({}:{}:{})", frame.function_name, frame.url, frame.line_number + 1, frame.column_number + 1))
+                .collect();
+            format!("{}\n{}", details.text, frames.join("\n"))
+        }));
+        report.page_errors.push(PageError { message, stack });
+    }
+
+    let mut requests = HashMap::new();
+    for event in drain(&mut request_events) {
+        requests.insert(event.request_id.inner().clone(), (event.request.method.clone(), event.request.url.clone()));
+    }
+    for event in drain(&mut response_events) {
+        if event.response.status >= 400 {
+            let method = requests.get(event.request_id.inner()).map(|(method, _)| method.clone()).unwrap_or_default();
+            report.failed_requests.push(FailedRequest {
+                method,
+                url: event.response.url.clone(),
+                reason: format!("status {} {}", event.response.status, event.response.status_text).trim().to_string(),
+            });
+        }
+    }
+    for event in drain(&mut failure_events) {
+        let (method, url) = requests.get(event.request_id.inner()).cloned().unwrap_or_default();
+        report.failed_requests.push(FailedRequest { method, url, reason: event.error_text.clone() });
+    }
+
+    if show_html {
+        report.html = Some(pretty_html(&page.content().await?));
+    }
+    page.close().await?;
+    Ok(report)
+}
+
+// Takes the events already buffered on a listener without waiting for more
+fn drain<T: chromiumoxide::cdp::IntoEventKind + Unpin>(events: &mut EventStream<T>) -> Vec<std::sync::Arc<T>> {
+    let mut drained = Vec::new();
+    while let Some(Some(event)) = events.next().now_or_never() {
+        drained.push(event);
+    }
+    drained
+}
+
+// Formats a console argument the way the browser console prints it
+fn remote_object_text(object: &RemoteObject) -> String {
+    match &object.value {
+        Some(serde_json::Value::String(value)) => value.clone(),
+        Some(value) => value.to_string(),
+        None => object.description.clone()
+            .or_else(|| object.unserializable_value.as_ref().map(|value| value.inner().clone()))
+            .unwrap_or_else(|| object.r#type.as_ref().to_string()),
+    }
+}
+
+// Puts each tag on its own indented line, leaving script, style and pre contents as they are
+fn pretty_html(html: &str) -> String {
+    const VOID_TAGS: [&str; 14] = ["area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param", "source", "track", "wbr"];
+    const RAW_TAGS: [&str; 4] = ["script", "style", "pre", "textarea"];
+
+    let mut pretty = String::new();
+    let mut depth = 0usize;
+    let mut rest = html;
+    while !rest.is_empty() {
+        let (token, remainder) = match rest.find('<') {
+            Some(0) => {
+                let end = rest.find('>').map(|end| end + 1).unwrap_or(rest.len());
+                rest.split_at(end)
+            }
+            Some(start) => rest.split_at(start),
+            None => rest.split_at(rest.len()),
+        };
+        rest = remainder;
+        let token = token.trim();
+        if token.is_empty() {
+            continue;
+        }
+
+        let tag_name: String = token.trim_start_matches(['<', '/'])
+            .chars()
+            .take_while(|c| c.is_ascii_alphanumeric() || *c == '-')
+            .collect::<String>()
+            .to_ascii_lowercase();
+        if token.starts_with("</") {
+            depth = depth.saturating_sub(1);
+        }
+        pretty += &format!("{}{}\n", "  ".repeat(depth), token);
+        let is_open_tag = token.starts_with('<') && !token.starts_with("</") && !token.starts_with("<!") && !token.ends_with("/>");
+        if is_open_tag && RAW_TAGS.contains(&tag_name.as_str()) {
+            let closing = format!("</{}", tag_name);
+            let end = rest.to_ascii_lowercase().find(&closing).unwrap_or(rest.len());
+            let contents = rest[..end].trim();
+            if !contents.is_empty() {
+                pretty += &format!("{}{}\n", "  ".repeat(depth + 1), contents);
+            }
+            rest = &rest[end..];
+            depth += 1;
+        } else if is_open_tag && !VOID_TAGS.contains(&tag_name.as_str()) {
+            depth += 1;
+        }
+    }
+    pretty
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pretty_html_keeps_raw_contents_with_non_ascii_text() {
+        // "İ" lowercases to more bytes, which used to shift where </script> was found
+        let html = "<html><body><script>const label = 'İİİ';</script><p>Done</p></body></html>";
+        assert_eq!(
+            pretty_html(html),
+            "<html>\n  <body>\n    <script>\n      const label = 'İİİ';\n    </script>\n    <p>\n      Done\n    </p>\n  </body>\n</html>\n"
+        );
+    }
+}
@@ -1,6 +1,22 @@
 pub mod prompt;
 pub mod extract_jsx;
 pub mod log_and_run;
+pub mod dev_server;
+pub mod readiness;
+pub mod git;
+pub mod transcript;
+pub mod verdict;
+pub mod expect;
+pub mod test_suite;
 pub mod get_feature;
 pub mod remove_feature;
-pub mod get_updated_functions;
\ No newline at end of file
+pub mod get_updated_functions;
+pub mod feature;
+pub mod validate;
+pub mod syntax;
+pub mod static_checks;
+pub mod context;
+pub mod prompt_budget;
+pub mod planner;
+pub mod cassette;
+pub mod service_client;
\ No newline at end of file
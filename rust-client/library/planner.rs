@@ -0,0 +1,309 @@
+use anyhow::{Context, Result}; // Importing Result and Context from anyhow crate
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::fs;
+use std::path::Path;
+use crate::library::prompt::{LlmProvider, Message};
+use crate::library::prompt_budget::TokenCounter;
+use crate::library::test_suite::output_tail;
+use crate::library::transcript::StepTranscript;
+use crate::library::validate::validate_feature;
+
+// Directories that hold dependencies, build output or VCS data rather than source
+const SKIPPED_DIRS: [&str; 8] = ["node_modules", ".git", ".next", "dist", "build", "out", "coverage", "runs"];
+const SOURCE_EXTENSIONS: [&str; 9] = ["js", "jsx", "ts", "tsx", "mjs", "cjs", "css", "json", "md"];
+// Files listed in the overview; larger repos are cut off with a note
+const MAX_LISTED_FILES: usize = 400;
+// Files whose contents tell the planner how the app is put together
+const KEY_FILES: [&str; 10] = [
+    "package.json", "app/layout.tsx", "app/layout.js", "app/page.tsx", "app/page.js",
+    "pages/index.tsx", "pages/index.js", "src/App.tsx", "src/App.jsx", "src/App.js",
+];
+// Times the planner is asked again after a plan the validator rejects
+const PLAN_RETRIES: usize = 2;
+// Lines of each attempt's errors shown to the planner when replanning
+const FAILURE_LINES: usize = 20;
+
+const PLANNER_SYSTEM_PROMPT: &str = "You are a senior React developer breaking a feature down into small steps for another developer. Each step changes exactly one file, and after each step the app still builds and the change can be seen by loading one page.";
+
+// One step as the planner describes it, before it becomes a spec step
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PlannedStep {
+    pub description: String,
+    // Path of the one file the step writes, relative to the repo
+    pub target: String,
+    // Other files the step needs to see, relative to the repo
+    #[serde(default)]
+    pub context_files: Vec<String>,
+    #[serde(default)]
+    pub test_path: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Plan {
+    steps: Vec<PlannedStep>,
+}
+
+// JSON schema of a plan, for JSON mode and tool calls
+pub fn plan_schema() -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "steps": {
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "properties": {
+                        "description": { "type": "string", "description": "What to change in the target file, specific enough to do without seeing the other steps" },
+                        "target": { "type": "string", "description": "Path of the one file this step creates or changes, relative to the repo" },
+                        "context_files": { "type": "array", "items": { "type": "string" }, "description": "Existing files the step needs to read, relative to the repo" },
+                        "test_path": { "type": ["string", "null"], "description": "URL of the page that shows the change, or null" }
+                    },
+                    "required": ["description", "target", "context_files", "test_path"],
+                    "additionalProperties": false
+                }
+            }
+        },
+        "required": ["steps"],
+        "additionalProperties": false
+    })
+}
+
+// The repo's file tree and the contents of its key files, within `budget` tokens
+pub fn repo_overview(repo_dir: &Path, budget: usize, counter: &TokenCounter) -> Result<String> {
+    let mut files = Vec::new();
+    list_source_files(repo_dir, Path::new(""), &mut files)
+        .with_context(|| format!("Failed to read {}", repo_dir.display()))?;
+    files.sort();
+    let total = files.len();
+    files.truncate(MAX_LISTED_FILES);
+
+    let mut overview = format!("Files in the repository:\n{}\n", files.join("\n"));
+    if total > files.len() {
+        overview += &format!("[truncated: {} more files]\n", total - files.len());
+    }
+    let mut used = counter.count(&overview);
+    for key_file in KEY_FILES {
+        let Ok(contents) = fs::read_to_string(repo_dir.join(key_file)) else {
+            continue;
+        };
+        let section = format!("\nContents of {}:\n{}\n", key_file, contents);
+        let tokens = counter.count(&section);
+        if used + tokens > budget {
+            continue;
+        }
+        used += tokens;
+        overview += &section;
+    }
+    Ok(overview)
+}
+
+// Asks for the steps of `feature` and returns it with them filled in, after
+// checking the result with the spec validator. A plan the validator rejects is
+// sent back with the problems found, a few times, before giving up.
+pub async fn plan_feature(provider: &dyn LlmProvider, feature: &Value, repo_dir: &Path, overview: &str, base_url: &str) -> Result<Value> {
+    let description = feature.get("description").and_then(Value::as_str)
+        .context("The feature spec needs a description to plan from")?;
+    let request = format!(
+        "Plan this feature: \"{}\"\n\n{}\nSplit it into the fewest small steps that each change one file, in the order they should be done. Later steps may use what earlier steps add. For each step give a description specific enough to do on its own, the target file's path relative to the repository (it may be a new file), the paths of existing files it needs to read, and the URL of the page that shows the change, starting with {} (null if no page shows it). Reply with only a JSON object with a \"steps\" array of objects with \"description\", \"target\", \"context_files\" and \"test_path\".",
+        description, overview, base_url
+    );
+    let messages = vec![Message::system(PLANNER_SYSTEM_PROMPT), Message::user(&request)];
+    let steps = request_steps(provider, messages, feature, repo_dir).await?;
+    let mut planned = feature.clone();
+    planned["steps"] = Value::Array(steps);
+    Ok(planned)
+}
+
+// Asks for steps to take the place of one that used up its attempts, given
+// what went wrong in each attempt and the repo as it is after rolling back.
+// The steps may split the work up or describe it differently.
+pub async fn replan_step(provider: &dyn LlmProvider, feature: &Value, failed: &StepTranscript, repo_dir: &Path, overview: &str, base_url: &str) -> Result<Vec<Value>> {
+    let description = feature.get("description").and_then(Value::as_str).unwrap_or("(no description)");
+    let target_contents = fs::read_to_string(repo_dir.join(&failed.target))
+        .map(|contents| format!("Contents of {} now:\n{}\n", failed.target, contents))
+        .unwrap_or_else(|_| format!("{} does not exist yet.\n", failed.target));
+    let request = format!(
+        "While building this feature: \"{}\"\n\nthis step failed after {} attempt(s), and its changes were rolled back:\n\nStep: {}\nTarget: {}\n\n{}\n{}\n{}\nReplace the step with steps that can succeed. Either split it into smaller steps that each change one file and can be seen working on their own, or, if one step is still right, rewrite its description to be clearer about what to do and what to avoid given the failures above. For each step give a description specific enough to do on its own, the target file's path relative to the repository (it may be a new file), the paths of existing files it needs to read, and the URL of the page that shows the change, starting with {} (null if no page shows it). Reply with only a JSON object with a \"steps\" array of objects with \"description\", \"target\", \"context_files\" and \"test_path\".",
+        description, failed.attempts.len(), failed.description, failed.target, failure_summary(failed), target_contents, overview, base_url
+    );
+    let messages = vec![Message::system(PLANNER_SYSTEM_PROMPT), Message::user(&request)];
+    request_steps(provider, messages, feature, repo_dir).await
+}
+
+// What went wrong in each attempt of a failed step, briefly
+fn failure_summary(failed: &StepTranscript) -> String {
+    let mut summary = String::from("What happened in each attempt:\n");
+    for (i, attempt) in failed.attempts.iter().enumerate() {
+        let reason = attempt.verdict.as_ref().map(|verdict| verdict.reason.as_str()).unwrap_or("no verdict");
+        summary += &format!("\nAttempt {}: {}\n", i + 1, reason);
+        for (heading, errors) in [("Static checks", &attempt.static_errors), ("Build errors", &attempt.build_errors), ("Test suite", &attempt.test_suite)] {
+            if !errors.trim().is_empty() {
+                summary += &format!("{}:\n{}\n", heading, output_tail(errors, FAILURE_LINES));
+            }
+        }
+    }
+    if let Some(error) = &failed.error {
+        summary += &format!("\nThe step stopped with: {}\n", error);
+    }
+    summary
+}
+
+// Asks until the reply is a plan that makes `feature` valid and only reads
+// files that exist or that an earlier step writes, returning its spec steps
+pub async fn request_steps(provider: &dyn LlmProvider, mut messages: Vec<Message>, feature: &Value, repo_dir: &Path) -> Result<Vec<Value>> {
+    let schema = plan_schema();
+    let mut problems = Vec::new();
+    for attempt in 0..=PLAN_RETRIES {
+        if attempt > 0 {
+            warn!("The plan was not usable, asking again: {}", problems.join("; "));
+            messages.push(Message::tool_result(&format!("The plan could not be used:\n{}", problems.join("\n"))));
+            messages.push(Message::user("Please reply with a corrected plan, as only the JSON object described above."));
+        }
+        let reply = provider.chat_structured(&messages, "report_plan", &schema).await?;
+        messages.push(Message::assistant(&reply));
+
+        let plan = match parse_plan(&reply) {
+            Ok(plan) => plan,
+            Err(err) => {
+                problems = vec![format!("{:#}", err)];
+                continue;
+            }
+        };
+        let steps: Vec<Value> = plan.iter().map(spec_step).collect();
+        let mut candidate = feature.clone();
+        candidate["steps"] = Value::Array(steps.clone());
+        problems = validate_feature(&candidate).iter().map(ToString::to_string).collect();
+        for (i, step) in plan.iter().enumerate() {
+            for path in &step.context_files {
+                let created_earlier = plan[..i].iter().any(|earlier| earlier.target == *path);
+                if !created_earlier && !repo_dir.join(path).is_file() {
+                    problems.push(format!("step {}: context file \"{}\" does not exist", i + 1, path));
+                }
+            }
+        }
+        if problems.is_empty() {
+            info!("Planned {} step(s).", steps.len());
+            return Ok(steps);
+        }
+    }
+    anyhow::bail!("The planner did not produce a valid plan:\n{}", problems.join("\n"))
+}
+
+// Reads the planner's reply: as a plan object, optionally in a ```json fence,
+// or as one embedded in other text
+pub fn parse_plan(reply: &str) -> Result<Vec<PlannedStep>> {
+    let mut text = reply.trim();
+    if let Some(fenced) = text.strip_prefix("```") {
+        let fenced = fenced.strip_prefix("json").unwrap_or(fenced);
+        text = fenced.strip_suffix("```").unwrap_or(fenced).trim();
+    }
+    let plan = match serde_json::from_str::<Plan>(text) {
+        Ok(plan) => plan,
+        Err(err) => {
+            let embedded = reply.find('{').zip(reply.rfind('}'))
+                .filter(|(start, end)| start < end)
+                .and_then(|(start, end)| serde_json::from_str::<Plan>(&reply[start..=end]).ok());
+            embedded.ok_or(err).context("The reply was not a JSON object with a \"steps\" array")?
+        }
+    };
+    if plan.steps.is_empty() {
+        anyhow::bail!("The plan has no steps");
+    }
+    Ok(plan.steps)
+}
+
+// A spec step for a planned one. The target is listed first, named by its
+// path so that `target` stays a repo path, followed by the context files.
+pub fn spec_step(planned: &PlannedStep) -> Value {
+    let mut files = vec![json!({ "fileName": planned.target, "filePath": planned.target, "isTarget": true })];
+    files.extend(planned.context_files.iter()
+        .filter(|path| **path != planned.target)
+        .map(|path| {
+            let file_name = Path::new(path).file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_else(|| path.clone());
+            json!({ "fileName": file_name, "filePath": path })
+        }));
+    let mut step = Map::new();
+    step.insert("description".to_string(), json!(planned.description));
+    step.insert("target".to_string(), json!(planned.target));
+    step.insert("files".to_string(), Value::Array(files));
+    if let Some(test_path) = &planned.test_path {
+        step.insert("testPath".to_string(), json!(test_path));
+    }
+    Value::Object(step)
+}
+
+fn list_source_files(repo_dir: &Path, relative: &Path, files: &mut Vec<String>) -> std::io::Result<()> {
+    for entry in fs::read_dir(repo_dir.join(relative))? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        let path = relative.join(&name);
+        if entry.file_type()?.is_dir() {
+            if !name.starts_with('.') && !SKIPPED_DIRS.contains(&name.as_str()) {
+                list_source_files(repo_dir, &path, files)?;
+            }
+        } else if path.extension().and_then(|extension| extension.to_str()).is_some_and(|extension| SOURCE_EXTENSIONS.contains(&extension))
+            && name != "package-lock.json" {
+            files.push(path.to_string_lossy().replace('\\', "/"));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::prompt::MockProvider;
+
+    fn repo_with(files: &[&str]) -> std::path::PathBuf {
+        let repo_dir = std::env::temp_dir().join(format!("autocode-planner-{}-{}", std::process::id(), files.len()));
+        for file in files {
+            let path = repo_dir.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "export default function Page() {}\n").unwrap();
+        }
+        repo_dir
+    }
+
+    #[tokio::test]
+    async fn plan_with_context_files_validates() {
+        let repo_dir = repo_with(&["app/page.js", "app/layout.js"]);
+        let plan = json!({ "steps": [
+            { "description": "Add a counter", "target": "app/counter.js", "context_files": ["app/layout.js"], "test_path": null },
+            { "description": "Show the counter", "target": "app/page.js", "context_files": ["app/counter.js", "app/page.js"], "test_path": "http://localhost:3000/" }
+        ] });
+        let provider = MockProvider::new(vec![plan.to_string()]);
+        let feature = json!({ "description": "Count clicks" });
+
+        let steps = request_steps(&provider, vec![Message::user("Plan it")], &feature, &repo_dir).await.unwrap();
+        fs::remove_dir_all(&repo_dir).unwrap();
+
+        assert_eq!(provider.requests().len(), 1, "the first plan should have been accepted");
+        let mut spec = feature.clone();
+        spec["steps"] = Value::Array(steps.clone());
+        assert!(validate_feature(&spec).is_empty());
+        assert_eq!(steps[1]["target"], "app/page.js");
+        assert_eq!(steps[1]["files"], json!([
+            { "fileName": "app/page.js", "filePath": "app/page.js", "isTarget": true },
+            { "fileName": "counter.js", "filePath": "app/counter.js" }
+        ]));
+    }
+
+    #[tokio::test]
+    async fn plan_reading_a_missing_file_is_sent_back() {
+        let repo_dir = repo_with(&["app/page.js"]);
+        let bad = json!({ "steps": [{ "description": "Show it", "target": "app/page.js", "context_files": ["app/missing.js"], "test_path": null }] });
+        let good = json!({ "steps": [{ "description": "Show it", "target": "app/page.js", "context_files": [], "test_path": null }] });
+        let provider = MockProvider::new(vec![bad.to_string(), good.to_string()]);
+
+        let steps = request_steps(&provider, vec![Message::user("Plan it")], &json!({}), &repo_dir).await.unwrap();
+        fs::remove_dir_all(&repo_dir).unwrap();
+
+        assert_eq!(steps.len(), 1);
+        let retry = provider.requests().last().unwrap().iter().map(|message| message.content.clone()).collect::<Vec<_>>().join("\n");
+        assert!(retry.contains("context file \"app/missing.js\" does not exist"), "{}", retry);
+    }
+}
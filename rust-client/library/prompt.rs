@@ -1,28 +1,282 @@
 use anyhow::{Context, Result}; // Importing Result and Context from anyhow crate
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use crate::library::feature::{LlmConfig, ProviderKind};
 
-pub async fn prompt(prompt: &str, api_key: &String) -> Result<String> {
-    let client = reqwest::Client::new();
-  
-    let request_data = serde_json::json!({
-        "model": "gpt-3.5-turbo",
-        "messages": [{ "role": "user", "content": prompt }],
-        "temperature": 0.7,
-    });
-  
-    let response = client
-        .post("https://api.openai.com/v1/chat/completions")
-        .header("Content-Type", "application/json")
-        .header("Authorization", format!("Bearer {}", api_key))
-        .json(&request_data)
-        .send()
-        .await?
-        .text()
-        .await?;
-    
-    let response_data: serde_json::Value = serde_json::from_str(&response)?;
-    let code = response_data["choices"][0]["message"]["content"]
-        .as_str()
-        .ok_or_else(|| anyhow::anyhow!("Response data does not contain expected content"))?;
-  
-    Ok(code.to_string())
-  }
\ No newline at end of file
+const DEFAULT_OPENAI_BASE_URL: &str = "https://api.openai.com/v1";
+const DEFAULT_ANTHROPIC_BASE_URL: &str = "https://api.anthropic.com/v1";
+pub const DEFAULT_OPENAI_MODEL: &str = "gpt-3.5-turbo";
+const DEFAULT_TEMPERATURE: f64 = 0.7;
+const DEFAULT_MAX_TOKENS: u32 = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    System,
+    User,
+    Assistant,
+    // Results of running the code, such as console logs and build errors
+    Tool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Message {
+    pub role: Role,
+    pub content: String,
+}
+
+impl Message {
+    pub fn system(content: &str) -> Self {
+        Message { role: Role::System, content: content.to_string() }
+    }
+
+    pub fn user(content: &str) -> Self {
+        Message { role: Role::User, content: content.to_string() }
+    }
+
+    pub fn assistant(content: &str) -> Self {
+        Message { role: Role::Assistant, content: content.to_string() }
+    }
+
+    pub fn tool_result(content: &str) -> Self {
+        Message { role: Role::Tool, content: content.to_string() }
+    }
+}
+
+// Chat APIs only accept tool results that answer one of their own tool calls,
+// so results are sent as labelled user turns instead
+fn wire_message(message: &Message) -> Message {
+    match message.role {
+        Role::Tool => Message::user(&format!("Tool result:\n{}", message.content)),
+        _ => message.clone(),
+    }
+}
+
+#[async_trait]
+pub trait LlmProvider: Send + Sync {
+    async fn chat(&self, messages: &[Message]) -> Result<String>;
+
+    // A reply that should be a JSON object matching `schema`. Providers that
+    // can't enforce it just chat, so callers must still parse defensively.
+    async fn chat_structured(&self, messages: &[Message], _name: &str, _schema: &serde_json::Value) -> Result<String> {
+        self.chat(messages).await
+    }
+}
+
+// Builds the provider described by an already merged feature/step config
+pub fn build_provider(config: &LlmConfig) -> Result<Box<dyn LlmProvider>> {
+    let temperature = config.temperature.unwrap_or(DEFAULT_TEMPERATURE);
+    match config.provider.unwrap_or(ProviderKind::OpenAi) {
+        ProviderKind::OpenAi => {
+            let api_key_env = config.api_key_env.as_deref().unwrap_or("CHATGPT_APIKEY");
+            let base_url = config.base_url.as_deref().unwrap_or(DEFAULT_OPENAI_BASE_URL);
+            // Local OpenAI-compatible servers usually don't need a key
+            let api_key = std::env::var(api_key_env).ok().filter(|key| !key.is_empty());
+            if api_key.is_none() && base_url == DEFAULT_OPENAI_BASE_URL {
+                anyhow::bail!("{} environment variable not found", api_key_env);
+            }
+            Ok(Box::new(OpenAiProvider {
+                client: reqwest::Client::new(),
+                base_url: base_url.trim_end_matches('/').to_string(),
+                api_key,
+                model: config.model.clone().unwrap_or_else(|| DEFAULT_OPENAI_MODEL.to_string()),
+                temperature,
+                max_tokens: config.max_tokens,
+            }))
+        }
+        ProviderKind::Anthropic => {
+            let api_key_env = config.api_key_env.as_deref().unwrap_or("ANTHROPIC_API_KEY");
+            let api_key = std::env::var(api_key_env)
+                .with_context(|| format!("{} environment variable not found", api_key_env))?;
+            let model = config.model.clone().context("A model is required for the anthropic provider")?;
+            Ok(Box::new(AnthropicProvider {
+                client: reqwest::Client::new(),
+                base_url: config.base_url.as_deref().unwrap_or(DEFAULT_ANTHROPIC_BASE_URL).trim_end_matches('/').to_string(),
+                api_key,
+                model,
+                temperature,
+                max_tokens: config.max_tokens.unwrap_or(DEFAULT_MAX_TOKENS),
+            }))
+        }
+        ProviderKind::Mock => Ok(Box::new(MockProvider::new(config.responses.clone().unwrap_or_default()))),
+    }
+}
+
+// Any server that speaks the OpenAI chat completions API, e.g. OpenAI, llama.cpp, Ollama or vLLM
+pub struct OpenAiProvider {
+    client: reqwest::Client,
+    base_url: String,
+    api_key: Option<String>,
+    model: String,
+    temperature: f64,
+    max_tokens: Option<u32>,
+}
+
+#[async_trait]
+impl LlmProvider for OpenAiProvider {
+    async fn chat(&self, messages: &[Message]) -> Result<String> {
+        self.complete(messages, None).await
+    }
+
+    async fn chat_structured(&self, messages: &[Message], name: &str, _schema: &serde_json::Value) -> Result<String> {
+        // JSON mode is the structured output most OpenAI-compatible servers support
+        let response_format = serde_json::json!({ "type": "json_object" });
+        match self.complete(messages, Some(response_format)).await {
+            Ok(reply) => Ok(reply),
+            Err(err) => {
+                log::warn!("JSON mode request for {} failed, asking without it: {:#}", name, err);
+                self.complete(messages, None).await
+            }
+        }
+    }
+}
+
+impl OpenAiProvider {
+    async fn complete(&self, messages: &[Message], response_format: Option<serde_json::Value>) -> Result<String> {
+        let messages: Vec<Message> = messages.iter().map(wire_message).collect();
+        let mut request_data = serde_json::json!({
+            "model": self.model,
+            "messages": messages,
+            "temperature": self.temperature,
+        });
+        if let Some(max_tokens) = self.max_tokens {
+            request_data["max_tokens"] = serde_json::json!(max_tokens);
+        }
+        if let Some(response_format) = response_format {
+            request_data["response_format"] = response_format;
+        }
+
+        let mut request = self.client
+            .post(format!("{}/chat/completions", self.base_url))
+            .header("Content-Type", "application/json");
+        if let Some(api_key) = &self.api_key {
+            request = request.header("Authorization", format!("Bearer {}", api_key));
+        }
+        let response = request
+            .json(&request_data)
+            .send()
+            .await?
+            .text()
+            .await?;
+
+        let response_data: serde_json::Value = serde_json::from_str(&response)?;
+        let code = response_data["choices"][0]["message"]["content"]
+            .as_str()
+            .ok_or_else(|| anyhow::anyhow!("Response data does not contain expected content: {}", response))?;
+
+        Ok(code.to_string())
+    }
+}
+
+pub struct AnthropicProvider {
+    client: reqwest::Client,
+    base_url: String,
+    api_key: String,
+    model: String,
+    temperature: f64,
+    max_tokens: u32,
+}
+
+#[async_trait]
+impl LlmProvider for AnthropicProvider {
+    async fn chat(&self, messages: &[Message]) -> Result<String> {
+        self.complete(messages, None).await
+    }
+
+    // Forces a call to a tool whose input is the object, and returns that input
+    async fn chat_structured(&self, messages: &[Message], name: &str, schema: &serde_json::Value) -> Result<String> {
+        self.complete(messages, Some((name, schema))).await
+    }
+}
+
+impl AnthropicProvider {
+    async fn complete(&self, messages: &[Message], tool: Option<(&str, &serde_json::Value)>) -> Result<String> {
+        // The messages API takes the system prompt separately from the conversation
+        let system: Vec<&str> = messages.iter()
+            .filter(|message| message.role == Role::System)
+            .map(|message| message.content.as_str())
+            .collect();
+        // Turns must alternate, so consecutive turns of one role are joined
+        let mut conversation: Vec<Message> = Vec::new();
+        for message in messages.iter().filter(|message| message.role != Role::System).map(wire_message) {
+            match conversation.last_mut() {
+                Some(last) if last.role == message.role => {
+                    last.content.push_str("\n\n");
+                    last.content.push_str(&message.content);
+                }
+                _ => conversation.push(message),
+            }
+        }
+        let mut request_data = serde_json::json!({
+            "model": self.model,
+            "messages": conversation,
+            "temperature": self.temperature,
+            "max_tokens": self.max_tokens,
+        });
+        if !system.is_empty() {
+            request_data["system"] = serde_json::json!(system.join("\n\n"));
+        }
+        if let Some((name, schema)) = tool {
+            request_data["tools"] = serde_json::json!([{ "name": name, "input_schema": schema }]);
+            request_data["tool_choice"] = serde_json::json!({ "type": "tool", "name": name });
+        }
+
+        let response = self.client
+            .post(format!("{}/messages", self.base_url))
+            .header("Content-Type", "application/json")
+            .header("x-api-key", &self.api_key)
+            .header("anthropic-version", "2023-06-01")
+            .json(&request_data)
+            .send()
+            .await?
+            .text()
+            .await?;
+
+        let response_data: serde_json::Value = serde_json::from_str(&response)?;
+        let blocks = response_data["content"]
+            .as_array()
+            .ok_or_else(|| anyhow::anyhow!("Response data does not contain expected content: {}", response))?;
+        if let Some(input) = blocks.iter().find(|block| block["type"] == "tool_use").map(|block| &block["input"]) {
+            return Ok(input.to_string());
+        }
+        let code: String = blocks.iter()
+            .filter_map(|block| block["text"].as_str())
+            .collect();
+
+        Ok(code)
+    }
+}
+
+// Replies with scripted responses in order and remembers what it was asked.
+// Clones share the same script, so a test can keep a handle for inspection.
+#[derive(Clone, Default)]
+pub struct MockProvider {
+    responses: Arc<Mutex<VecDeque<String>>>,
+    requests: Arc<Mutex<Vec<Vec<Message>>>>,
+}
+
+impl MockProvider {
+    pub fn new(responses: Vec<String>) -> Self {
+        MockProvider {
+            responses: Arc::new(Mutex::new(responses.into())),
+            requests: Arc::default(),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn requests(&self) -> Vec<Vec<Message>> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl LlmProvider for MockProvider {
+    async fn chat(&self, messages: &[Message]) -> Result<String> {
+        self.requests.lock().unwrap().push(messages.to_vec());
+        self.responses.lock().unwrap()
+            .pop_front()
+            .ok_or_else(|| anyhow::anyhow!("Mock provider has no scripted responses left"))
+    }
+}
@@ -0,0 +1,229 @@
+use anyhow::Result; // Importing Result from anyhow crate
+use oxc_allocator::Allocator;
+use oxc_ast::ast::{Declaration, ExportDefaultDeclarationKind, Statement};
+use oxc_parser::Parser;
+use oxc_span::GetSpan;
+use regex::Regex;
+use std::collections::HashSet;
+use tiktoken_rs::CoreBPE;
+use crate::library::feature::{FileRef, LlmConfig, ProviderKind};
+use crate::library::prompt::DEFAULT_OPENAI_MODEL;
+use crate::library::syntax::source_type_for;
+
+// Kept free for the system prompt and the framing of each message
+const FRAMING_TOKENS: usize = 200;
+// Kept free for the reply when no maxTokens is set, but never more than a
+// quarter of the window, so a small or unknown model still has room to ask
+const DEFAULT_REPLY_TOKENS: usize = 4096;
+const DEFAULT_REPLY_SHARE: usize = 4;
+const ANTHROPIC_CONTEXT_WINDOW: usize = 200_000;
+// OpenAI models newer than tiktoken's table, which guesses 4096 for them
+const RECENT_OPENAI_MODELS: [&str; 6] = ["gpt-4o", "gpt-4.1", "gpt-5", "o1", "o3", "o4"];
+const RECENT_OPENAI_CONTEXT_WINDOW: usize = 128_000;
+// Scripted replies have no real limit
+const MOCK_CONTEXT_WINDOW: usize = 1_000_000;
+// Claude's tokenizer isn't published, and it counts code about this much
+// higher than cl100k does
+const ANTHROPIC_TOKEN_FACTOR: f64 = 1.2;
+
+// Counts tokens the way the chosen model does, or as close as can be done offline
+pub struct TokenCounter {
+    bpe: CoreBPE,
+    factor: f64,
+}
+
+impl TokenCounter {
+    pub fn for_config(config: &LlmConfig) -> Result<Self> {
+        let model = model_name(config);
+        let bpe = match tiktoken_rs::get_bpe_from_model(&model) {
+            Ok(bpe) => bpe,
+            Err(_) => tiktoken_rs::cl100k_base()?,
+        };
+        let factor = if config.provider == Some(ProviderKind::Anthropic) { ANTHROPIC_TOKEN_FACTOR } else { 1.0 };
+        Ok(TokenCounter { bpe, factor })
+    }
+
+    pub fn count(&self, text: &str) -> usize {
+        let tokens = self.bpe.encode_with_special_tokens(text).len();
+        (tokens as f64 * self.factor).ceil() as usize
+    }
+}
+
+// How much of the model's context a step's first prompt may use
+pub struct PromptBudget {
+    pub model: String,
+    pub context_window: usize,
+    pub reply_tokens: usize,
+    pub counter: TokenCounter,
+}
+
+impl PromptBudget {
+    pub fn for_config(config: &LlmConfig) -> Result<Self> {
+        let model = model_name(config);
+        let context_window = config.context_window.unwrap_or_else(|| match config.provider {
+            Some(ProviderKind::Anthropic) => ANTHROPIC_CONTEXT_WINDOW,
+            Some(ProviderKind::Mock) => MOCK_CONTEXT_WINDOW,
+            _ if RECENT_OPENAI_MODELS.iter().any(|prefix| model.starts_with(prefix)) => RECENT_OPENAI_CONTEXT_WINDOW,
+            _ => tiktoken_rs::model::get_context_size(&model),
+        });
+        Ok(PromptBudget {
+            context_window,
+            reply_tokens: config.max_tokens.map(|max_tokens| max_tokens as usize).unwrap_or(DEFAULT_REPLY_TOKENS.min(context_window / DEFAULT_REPLY_SHARE)),
+            counter: TokenCounter::for_config(config)?,
+            model,
+        })
+    }
+
+    // Tokens left for the prompt itself
+    pub fn available(&self) -> usize {
+        self.context_window.saturating_sub(self.reply_tokens + FRAMING_TOKENS)
+    }
+}
+
+// Puts `header` and the whole target file first, then as much of each helper
+// as still fits: the whole file, else the definitions the target uses with
+// signatures of the rest, else signatures only, else a note that it was left
+// out. Fails when the header and target alone don't fit.
+pub fn assemble_prompt(header: &str, target: &FileRef, helpers: &[FileRef], budget: &PromptBudget) -> Result<String> {
+    let target_contents = target.file_contents.as_deref().unwrap_or("No file contents");
+    let mut prompt = header.to_string();
+    prompt.push_str(&file_section(target, target_contents));
+
+    let available = budget.available();
+    let minimum = budget.counter.count(&prompt);
+    if minimum > available {
+        anyhow::bail!(
+            "The prompt needs {} tokens for the step and its target file alone, but {} only has {} to spare ({} in its context window, less {} kept for the reply and message framing). Use a model with a larger context window, lower maxTokens, or split {} into smaller files.",
+            minimum, budget.model, available, budget.context_window, budget.reply_tokens + FRAMING_TOKENS, target.file_name
+        );
+    }
+
+    let referenced = identifiers(target_contents);
+    let mut used = minimum;
+    for helper in helpers {
+        let contents = helper.file_contents.as_deref().unwrap_or("No file contents");
+        let mut versions = vec![file_section(helper, contents)];
+        if let Some(trimmed) = trim_to_referenced(contents, &helper.file_name, Some(&referenced)) {
+            versions.push(file_section(helper, &format!("{}\n[truncated: the parts {} uses are shown in full, the rest as signatures]", trimmed, target.file_name)));
+        }
+        if let Some(signatures) = trim_to_referenced(contents, &helper.file_name, None) {
+            versions.push(file_section(helper, &format!("{}\n[truncated: signatures only]", signatures)));
+        }
+        versions.push(format!(" [truncated: {} was left out to fit the model's context]\n", helper.file_name));
+
+        for version in versions {
+            let tokens = budget.counter.count(&version);
+            if used + tokens <= available {
+                used += tokens;
+                prompt.push_str(&version);
+                break;
+            }
+        }
+    }
+    Ok(prompt)
+}
+
+fn file_section(file: &FileRef, contents: &str) -> String {
+    format!(" Here is the current {} located at {}: \"{}\"\n", file.file_name, file.file_path, contents)
+}
+
+// Every word in `code` that could name something it uses
+fn identifiers(code: &str) -> HashSet<String> {
+    let identifier = Regex::new(r"[A-Za-z_$][\w$]*").unwrap();
+    identifier.find_iter(code).map(|found| found.as_str().to_string()).collect()
+}
+
+// The file's top-level definitions, those named in `referenced` in full and
+// the rest cut to their first line. Imports are dropped. None if the file
+// doesn't parse or nothing would be cut.
+fn trim_to_referenced(code: &str, file_name: &str, referenced: Option<&HashSet<String>>) -> Option<String> {
+    let allocator = Allocator::default();
+    let parsed = Parser::new(&allocator, code, source_type_for(file_name)).parse();
+    if !parsed.errors.is_empty() {
+        return None;
+    }
+    let mut kept = Vec::new();
+    for statement in &parsed.program.body {
+        if matches!(statement, Statement::ImportDeclaration(_)) {
+            continue;
+        }
+        let text = statement.span().source_text(code);
+        let in_full = referenced.is_some_and(|referenced| statement_names(statement).iter().any(|name| referenced.contains(name)));
+        if in_full || !text.contains('\n') {
+            kept.push(text.to_string());
+        } else {
+            kept.push(format!("{} …", text.lines().next().unwrap_or_default().trim_end()));
+        }
+    }
+    let trimmed = kept.join("\n");
+    (trimmed.len() < code.trim().len()).then_some(trimmed)
+}
+
+// Names a top-level statement declares
+fn statement_names(statement: &Statement) -> Vec<String> {
+    let declaration = match statement {
+        Statement::ExportNamedDeclaration(export) => export.declaration.as_ref(),
+        Statement::ExportDefaultDeclaration(export) => {
+            let name = match &export.declaration {
+                ExportDefaultDeclarationKind::FunctionDeclaration(function) => function.id.as_ref().map(|id| id.name.to_string()),
+                ExportDefaultDeclarationKind::ClassDeclaration(class) => class.id.as_ref().map(|id| id.name.to_string()),
+                _ => None,
+            };
+            return name.into_iter().collect();
+        }
+        _ => statement.as_declaration(),
+    };
+    let Some(declaration) = declaration else {
+        return Vec::new();
+    };
+    match declaration {
+        Declaration::FunctionDeclaration(function) => function.id.iter().map(|id| id.name.to_string()).collect(),
+        Declaration::ClassDeclaration(class) => class.id.iter().map(|id| id.name.to_string()).collect(),
+        Declaration::VariableDeclaration(variables) => variables.declarations.iter()
+            .filter_map(|declarator| declarator.id.get_identifier_name().map(|name| name.to_string()))
+            .collect(),
+        Declaration::TSInterfaceDeclaration(interface) => vec![interface.id.name.to_string()],
+        Declaration::TSTypeAliasDeclaration(alias) => vec![alias.id.name.to_string()],
+        Declaration::TSEnumDeclaration(declaration) => vec![declaration.id.name.to_string()],
+        _ => Vec::new(),
+    }
+}
+
+fn model_name(config: &LlmConfig) -> String {
+    config.model.clone().unwrap_or_else(|| match config.provider {
+        Some(ProviderKind::OpenAi) | None => DEFAULT_OPENAI_MODEL.to_string(),
+        Some(ProviderKind::Anthropic) => "the Anthropic model".to_string(),
+        Some(ProviderKind::Mock) => "the mock provider".to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(provider: ProviderKind, model: &str) -> LlmConfig {
+        LlmConfig { provider: Some(provider), model: Some(model.to_string()), ..LlmConfig::default() }
+    }
+
+    #[test]
+    fn unknown_model_still_has_room_for_a_prompt() {
+        let budget = PromptBudget::for_config(&config(ProviderKind::OpenAi, "llama3")).unwrap();
+        assert_eq!(budget.context_window, 4096);
+        assert_eq!(budget.reply_tokens, 1024);
+        assert_eq!(budget.available(), 4096 - 1024 - FRAMING_TOKENS);
+    }
+
+    #[test]
+    fn known_model_keeps_the_default_reply_tokens() {
+        let budget = PromptBudget::for_config(&config(ProviderKind::OpenAi, "gpt-4o-mini")).unwrap();
+        assert_eq!(budget.context_window, RECENT_OPENAI_CONTEXT_WINDOW);
+        assert_eq!(budget.reply_tokens, DEFAULT_REPLY_TOKENS);
+    }
+
+    #[test]
+    fn max_tokens_and_context_window_are_used_as_given() {
+        let budget = PromptBudget::for_config(&LlmConfig { max_tokens: Some(3000), context_window: Some(8192), ..config(ProviderKind::OpenAi, "llama3") }).unwrap();
+        assert_eq!(budget.reply_tokens, 3000);
+        assert_eq!(budget.available(), 8192 - 3000 - FRAMING_TOKENS);
+    }
+}
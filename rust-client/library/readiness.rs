@@ -0,0 +1,67 @@
+use log::debug;
+use std::time::{Duration, Instant};
+use crate::library::dev_server::{CompileEvent, CompileWatch};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Readiness {
+    // The page is served with the current code
+    Ready,
+    // The dev server could not build the current code
+    CompileError(String),
+    TimedOut,
+}
+
+// Polls `test_path` until it answers 200, or until it answers at all once the
+// dev server reports a finished compile (a page that throws while rendering is
+// still served). Requesting the page is what makes Next.js compile it.
+pub async fn wait_until_served(test_path: &str, mut watch: Option<&mut CompileWatch>, timeout: Duration) -> Readiness {
+    let client = reqwest::Client::new();
+    let deadline = Instant::now() + timeout;
+    let mut compiled = false;
+    loop {
+        match check_watch(&mut watch).await {
+            Some(CompileEvent::Failed(report)) => return Readiness::CompileError(report),
+            Some(CompileEvent::Compiled) => compiled = true,
+            None => {}
+        }
+
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        match client.get(test_path).timeout(remaining.max(POLL_INTERVAL)).send().await {
+            Ok(response) if response.status().is_success() || compiled => {
+                // The compile output is printed before the response is sent
+                if let Some(CompileEvent::Failed(report)) = check_watch(&mut watch).await {
+                    return Readiness::CompileError(report);
+                }
+                return Readiness::Ready;
+            }
+            Ok(response) => debug!("{} answered {}, waiting for it to compile", test_path, response.status()),
+            Err(err) => debug!("{} is not up yet: {}", test_path, err),
+        }
+
+        if Instant::now() >= deadline {
+            return Readiness::TimedOut;
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+// Waits for something to listen on a local port, for servers without a test page
+pub async fn wait_for_port(port: u16, timeout: Duration) -> bool {
+    let deadline = Instant::now() + timeout;
+    while Instant::now() < deadline {
+        if tokio::net::TcpStream::connect(("127.0.0.1", port)).await.is_ok() {
+            return true;
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+    false
+}
+
+async fn check_watch(watch: &mut Option<&mut CompileWatch>) -> Option<CompileEvent> {
+    match watch {
+        Some(watch) => watch.check().await,
+        None => None,
+    }
+}
@@ -1,19 +1,17 @@
-use serde_json::{json, Value};
-use anyhow::{Context, Result};
+use anyhow::Result;
+use crate::library::service_client::ServiceClient;
 
-pub async fn remove_feature(doc_id: &str) -> Result<()> {
-    let url = format!("http://localhost:4000/api/remove-feature?doc-id={}", doc_id);
-  
-    let client = reqwest::Client::new();
-    let response = client
-        .delete(&url)
+pub async fn remove_feature(service: &ServiceClient, doc_id: &str) -> Result<()> {
+    let response = service
+        .delete("/api/remove-feature")
+        .query(&[("doc-id", doc_id)])
         .send()
         .await?;
     
     let status = response.status();
   
     if status.is_success() {
-        println!("Feature removed successfully");
+        log::info!("Feature removed successfully");
         return Ok(());
     }
   
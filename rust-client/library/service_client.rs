@@ -0,0 +1,95 @@
+use anyhow::{Context, Result}; // Importing Result and Context from anyhow crate
+use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION};
+use reqwest::{Client, RequestBuilder};
+use std::time::Duration;
+
+// Where express-autocode-api listens when nothing says otherwise
+pub const DEFAULT_SERVICE_PORT: u16 = 4000;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+// How to reach express-autocode-api
+#[derive(Debug, Clone)]
+pub struct ServiceConfig {
+    pub base_url: String,
+    // For a whole request, including a log-and-run's browser session
+    pub timeout: Duration,
+    // Sent as a bearer token with every request
+    pub token: Option<String>,
+}
+
+impl ServiceConfig {
+    // The base URL is `url` if given, else AUTOCODE_API_URL, else localhost on
+    // PORT, else localhost:4000. Each is looked up in the environment, then in
+    // .env. The token comes from AUTOCODE_API_TOKEN.
+    pub fn resolve(url: Option<&str>, timeout: Duration) -> ServiceConfig {
+        let base_url = url.map(str::to_string)
+            .or_else(|| env_or_dotenv("AUTOCODE_API_URL"))
+            .or_else(|| env_or_dotenv("PORT").map(|port| format!("http://localhost:{}", port.trim())))
+            .unwrap_or_else(|| format!("http://localhost:{}", DEFAULT_SERVICE_PORT));
+        ServiceConfig {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            timeout,
+            token: env_or_dotenv("AUTOCODE_API_TOKEN").filter(|token| !token.is_empty()),
+        }
+    }
+}
+
+// One pooled HTTP client for every call to express-autocode-api
+#[derive(Debug, Clone)]
+pub struct ServiceClient {
+    client: Client,
+    base_url: String,
+}
+
+impl ServiceClient {
+    pub fn new(config: &ServiceConfig) -> Result<Self> {
+        let url = url::Url::parse(&config.base_url)
+            .with_context(|| format!("Invalid express-autocode-api URL: {}", config.base_url))?;
+        let mut headers = HeaderMap::new();
+        if let Some(token) = &config.token {
+            let mut value = HeaderValue::from_str(&format!("Bearer {}", token))
+                .context("AUTOCODE_API_TOKEN can't be sent in a header")?;
+            value.set_sensitive(true);
+            headers.insert(AUTHORIZATION, value);
+        }
+        let client = Client::builder()
+            .default_headers(headers)
+            .timeout(config.timeout)
+            .connect_timeout(CONNECT_TIMEOUT)
+            .build()
+            .context("Failed to build the HTTP client")?;
+        Ok(ServiceClient { client, base_url: url.as_str().trim_end_matches('/').to_string() })
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base_url, path)
+    }
+
+    // The port the service listens on, for starting a local copy of it
+    pub fn port(&self) -> Option<u16> {
+        url::Url::parse(&self.base_url).ok().and_then(|url| url.port_or_known_default())
+    }
+
+    pub fn get(&self, path: &str) -> RequestBuilder {
+        self.client.get(self.url(path))
+    }
+
+    pub fn post(&self, path: &str) -> RequestBuilder {
+        self.client.post(self.url(path))
+    }
+
+    pub fn delete(&self, path: &str) -> RequestBuilder {
+        self.client.delete(self.url(path))
+    }
+}
+
+// Reads .env without loading it, since the run loads it later, after the
+// feature may have replaced it
+fn env_or_dotenv(key: &str) -> Option<String> {
+    std::env::var(key).ok().or_else(|| {
+        dotenvy::from_filename_iter(".env").ok()?
+            .flatten()
+            .find(|(name, _)| name == key)
+            .map(|(_, value)| value)
+    })
+}
@@ -0,0 +1,91 @@
+use std::path::Path;
+use std::time::Duration;
+use crate::library::extract_jsx::NO_CODE_FOUND;
+use oxc_span::SourceType;
+use crate::library::syntax::first_syntax_error;
+use crate::library::test_suite::{output_tail, run_shell};
+
+// Lines of a checker's output shown to the model
+const DIAGNOSTIC_LINES: usize = 60;
+
+// A typecheck or lint command run on the written file
+#[derive(Debug, Clone, PartialEq)]
+pub struct StaticCheck {
+    pub name: &'static str,
+    pub command: String,
+}
+
+// Why extracted code can't be written, or None if it parses. Catches the
+// extractor's own "no valid JSX" message as well as code that doesn't parse.
+pub fn syntax_problem(code: &str, source_type: SourceType) -> Option<String> {
+    if code.trim().is_empty() || code.starts_with(NO_CODE_FOUND) {
+        return Some("No code could be found in the reply.".to_string());
+    }
+    let error = first_syntax_error(code, source_type)?;
+    let offset = error.offset.min(code.len());
+    let line_number = code[..offset].matches('\n').count() + 1;
+    let line_start = code[..offset].rfind('\n').map(|index| index + 1).unwrap_or(0);
+    let column = code[line_start..offset].chars().count() + 1;
+    let line = code[line_start..].lines().next().unwrap_or_default();
+    Some(format!("Syntax error at line {}, column {}: {}\n{}", line_number, column, error.message, line))
+}
+
+// Runs each check in turn, stopping at the first that fails, and returns its
+// diagnostics. `{file}` in a command is replaced with `target_path`, the
+// target's path relative to `repo_dir`.
+pub async fn run_static_checks(checks: &[StaticCheck], repo_dir: &Path, target_path: &str, timeout: Duration) -> Option<String> {
+    for check in checks {
+        let command = check_command(&check.command, target_path);
+        let problem = match run_shell(&command, repo_dir, timeout).await {
+            Ok(output) if output.exit_code == Some(0) => continue,
+            Ok(output) => match output.exit_code {
+                Some(code) => format!("`{}` exited with code {}:\n{}", command, code, output_tail(&format!("{}{}", output.stdout, output.stderr), DIAGNOSTIC_LINES)),
+                None => format!("`{}` did not finish within {:?}.", command, timeout),
+            },
+            Err(err) => format!("{:#}", err),
+        };
+        log::info!("The {} check failed.", check.name);
+        return Some(format!("{} failed. {}", check.name, problem));
+    }
+    None
+}
+
+// The command with `{file}` replaced by the path, quoted for sh
+fn check_command(command: &str, target_path: &str) -> String {
+    command.replace("{file}", &shell_quote(target_path))
+}
+
+fn shell_quote(text: &str) -> String {
+    let plain = !text.is_empty() && text.chars().all(|c| c.is_ascii_alphanumeric() || "/._-+,:@%=".contains(c));
+    if plain {
+        text.to_string()
+    } else {
+        format!("'{}'", text.replace('\'', "'\\''"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_is_replaced_with_the_quoted_path() {
+        assert_eq!(check_command("npx eslint {file}", "app/page.js"), "npx eslint app/page.js");
+        assert_eq!(check_command("npx eslint {file}", "app/(home)/it's page.js"), "npx eslint 'app/(home)/it'\\''s page.js'");
+    }
+
+    #[tokio::test]
+    async fn checks_see_the_target_at_its_path_in_the_repo() {
+        let repo_dir = std::env::temp_dir().join(format!("autocode-static-checks-{}", std::process::id()));
+        std::fs::create_dir_all(repo_dir.join("app/(home)")).unwrap();
+        std::fs::write(repo_dir.join("app/(home)/page.js"), "").unwrap();
+        let check = StaticCheck { name: "Lint", command: "test -f {file} || { echo missing {file}; exit 1; }".to_string() };
+
+        let passed = run_static_checks(std::slice::from_ref(&check), &repo_dir, "app/(home)/page.js", Duration::from_secs(10)).await;
+        let failed = run_static_checks(&[check], &repo_dir, "page.js", Duration::from_secs(10)).await;
+        std::fs::remove_dir_all(&repo_dir).unwrap();
+
+        assert_eq!(passed, None);
+        assert!(failed.is_some_and(|problem| problem.contains("missing page.js")));
+    }
+}
@@ -0,0 +1,48 @@
+use oxc_allocator::Allocator;
+use oxc_parser::Parser;
+use oxc_span::SourceType;
+use std::path::Path;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxError {
+    pub offset: usize,
+    pub message: String,
+}
+
+// Parser mode for a file, from its extension. .ts and .tsx files get
+// TypeScript, everything else the JSX-enabled ES module dialect the TS
+// extractJSX parsed with acorn-jsx, since .js components often hold JSX.
+pub fn source_type_for(file_name: &str) -> SourceType {
+    match extension(file_name) {
+        "ts" | "mts" | "cts" => SourceType::ts(),
+        "tsx" => SourceType::tsx(),
+        _ => SourceType::jsx(),
+    }
+}
+
+// The Markdown fence language for a file's code
+pub fn code_language(file_name: &str) -> &'static str {
+    match extension(file_name) {
+        "ts" | "mts" | "cts" => "ts",
+        "tsx" => "tsx",
+        _ => "jsx",
+    }
+}
+
+fn extension(file_name: &str) -> &str {
+    Path::new(file_name).extension().and_then(|extension| extension.to_str()).unwrap_or_default()
+}
+
+// Returns the first syntax error in `code`, or None if it parses cleanly
+pub fn first_syntax_error(code: &str, source_type: SourceType) -> Option<SyntaxError> {
+    let allocator = Allocator::default();
+    let parsed = Parser::new(&allocator, code, source_type).parse();
+    parsed.errors.iter()
+        .map(|error| SyntaxError {
+            offset: error.labels.as_ref()
+                .and_then(|labels| labels.iter().map(|label| label.offset()).min())
+                .unwrap_or(0),
+            message: error.message.to_string(),
+        })
+        .min_by_key(|error| error.offset)
+}
@@ -0,0 +1,230 @@
+use anyhow::{Context, Result}; // Importing Result and Context from anyhow crate
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::Stdio;
+use std::time::Duration;
+use crate::library::dev_server::kill_group;
+
+// Lines of raw output kept when the failures can't be parsed out of it
+const OUTPUT_TAIL_LINES: usize = 60;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TestFailure {
+    pub name: String,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SuiteResult {
+    pub command: String,
+    // None when the command was killed, e.g. for taking too long
+    pub exit_code: Option<i32>,
+    pub passed: bool,
+    pub failures: Vec<TestFailure>,
+    // The end of stdout and stderr together
+    pub output_tail: String,
+}
+
+impl SuiteResult {
+    // What went wrong, for the verdict and the repair prompt
+    pub fn summary(&self) -> String {
+        if self.passed {
+            return format!("`{}` passed.", self.command);
+        }
+        let status = match self.exit_code {
+            Some(code) => format!("exited with code {}", code),
+            None => "was stopped before it finished".to_string(),
+        };
+        let mut summary = format!("`{}` {}.", self.command, status);
+        if self.failures.is_empty() && !self.output_tail.is_empty() {
+            summary += &format!(" The end of its output:\n{}", self.output_tail);
+        } else if !self.failures.is_empty() {
+            summary += " Failing tests:";
+            for failure in &self.failures {
+                summary += &format!("\n- {}", failure.name);
+                if !failure.message.is_empty() {
+                    summary += &format!(": {}", failure.message);
+                }
+            }
+        }
+        summary
+    }
+}
+
+// Runs the project's own test command in `repo_dir`. Its exit code decides
+// whether it passed. Failing tests are read from a JUnit XML report when
+// `junit_report` names one, otherwise from TAP or JUnit XML in the output.
+pub async fn run_test_command(command: &str, repo_dir: &Path, junit_report: Option<&Path>, timeout: Duration) -> Result<SuiteResult> {
+    // A report left by an earlier run would be read as this run's
+    if let Some(report_path) = junit_report {
+        match std::fs::remove_file(repo_dir.join(report_path)) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                return Err(err).with_context(|| format!("Failed to remove the old JUnit report {}", report_path.display()));
+            }
+            _ => {}
+        }
+    }
+    let CommandOutput { exit_code, stdout, stderr } = run_shell(command, repo_dir, timeout).await?;
+
+    let mut failures = Vec::new();
+    if let Some(report_path) = junit_report {
+        match std::fs::read_to_string(repo_dir.join(report_path)) {
+            Ok(xml) => failures = parse_junit(&xml).with_context(|| format!("Failed to parse {}", report_path.display()))?,
+            Err(err) => log::warn!("No JUnit report at {}: {}", report_path.display(), err),
+        }
+    }
+    if failures.is_empty() {
+        failures = parse_tap(&stdout);
+    }
+    if failures.is_empty() {
+        if let Some(xml) = embedded_junit(&stdout) {
+            failures = parse_junit(xml).unwrap_or_default();
+        }
+    }
+
+    let output_tail = output_tail(&format!("{}{}", stdout, stderr), OUTPUT_TAIL_LINES);
+    Ok(SuiteResult {
+        command: command.to_string(),
+        exit_code,
+        passed: exit_code == Some(0),
+        failures,
+        output_tail,
+    })
+}
+
+pub struct CommandOutput {
+    // None when the command was killed for taking too long
+    pub exit_code: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+// Runs `command` with sh in `repo_dir`, in its own process group so that it
+// can be stopped with everything it started once `timeout` passes
+pub async fn run_shell(command: &str, repo_dir: &Path, timeout: Duration) -> Result<CommandOutput> {
+    let mut shell = std::process::Command::new("sh");
+    shell.arg("-c")
+        .arg(command)
+        .current_dir(repo_dir)
+        // Keeps Jest and Vitest out of watch mode
+        .env("CI", "true")
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .process_group(0);
+    let child = tokio::process::Command::from(shell)
+        .kill_on_drop(true)
+        .spawn()
+        .with_context(|| format!("Failed to run `{}`", command))?;
+    let pgid = child.id().unwrap_or(0) as i32;
+
+    match tokio::time::timeout(timeout, child.wait_with_output()).await {
+        Ok(output) => {
+            let output = output.with_context(|| format!("Failed to run `{}`", command))?;
+            Ok(CommandOutput {
+                exit_code: output.status.code(),
+                stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+                stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+            })
+        }
+        Err(_) => {
+            // Test runners start workers, so the whole group has to go
+            kill_group(pgid);
+            Ok(CommandOutput { exit_code: None, stdout: String::new(), stderr: String::new() })
+        }
+    }
+}
+
+// The last `max_lines` lines of `output`
+pub fn output_tail(output: &str, max_lines: usize) -> String {
+    let lines: Vec<&str> = output.lines().collect();
+    lines[lines.len().saturating_sub(max_lines)..].join("\n")
+}
+
+// `not ok` lines of TAP output, with the message from their YAML block if any.
+// Tests marked `# TODO` or `# SKIP` are expected to fail, so they're left out.
+pub fn parse_tap(output: &str) -> Vec<TestFailure> {
+    let not_ok = Regex::new(r"^\s*not ok\b\s*\d*\s*(?:-\s*)?(.*?)(?:\s*#\s*(.*))?$").unwrap();
+    let lines: Vec<&str> = output.lines().collect();
+    let mut failures = Vec::new();
+    for (i, line) in lines.iter().enumerate() {
+        let Some(captures) = not_ok.captures(line) else {
+            continue;
+        };
+        let directive = captures.get(2).map(|directive| directive.as_str().to_ascii_uppercase()).unwrap_or_default();
+        if directive.starts_with("TODO") || directive.starts_with("SKIP") {
+            continue;
+        }
+        let mut message = Vec::new();
+        if lines.get(i + 1).is_some_and(|next| next.trim() == "---") {
+            for diagnostic in &lines[i + 2..] {
+                if diagnostic.trim() == "..." {
+                    break;
+                }
+                message.push(diagnostic.trim());
+            }
+        }
+        failures.push(TestFailure { name: captures[1].to_string(), message: message.join(" ") });
+    }
+    failures
+}
+
+// Test cases with a <failure> or <error> in a JUnit XML report
+pub fn parse_junit(xml: &str) -> Result<Vec<TestFailure>> {
+    let document = roxmltree::Document::parse(xml)?;
+    let mut failures = Vec::new();
+    for testcase in document.descendants().filter(|node| node.has_tag_name("testcase")) {
+        let Some(problem) = testcase.children().find(|node| node.has_tag_name("failure") || node.has_tag_name("error")) else {
+            continue;
+        };
+        let name = match (testcase.attribute("classname"), testcase.attribute("name")) {
+            (Some(classname), Some(name)) if !classname.is_empty() => format!("{} › {}", classname, name),
+            (_, name) => name.unwrap_or("unnamed test").to_string(),
+        };
+        let message = problem.attribute("message")
+            .map(str::to_string)
+            .or_else(|| problem.text().map(|text| text.trim().lines().next().unwrap_or_default().to_string()))
+            .unwrap_or_default();
+        failures.push(TestFailure { name, message });
+    }
+    Ok(failures)
+}
+
+fn embedded_junit(output: &str) -> Option<&str> {
+    let start = output.find("<testsuite")?;
+    let start = output[..start].rfind("<?xml").unwrap_or(start);
+    let end = output.rfind("</testsuites>").map(|end| end + "</testsuites>".len())
+        .or_else(|| output.rfind("</testsuite>").map(|end| end + "</testsuite>".len()))?;
+    (end > start).then(|| &output[start..end])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tap_failures_leave_out_todo_and_skip() {
+        let output = "TAP version 13\nok 1 - adds\nnot ok 2 - counts clicks\n  ---\n  message: expected 1\n  ...\nnot ok 3 - renders # TODO not built yet\nnot ok 4 - loads # skip no network\n1..4\n";
+        assert_eq!(parse_tap(output), [TestFailure { name: "counts clicks".to_string(), message: "message: expected 1".to_string() }]);
+    }
+
+    #[tokio::test]
+    async fn exit_code_decides_and_old_reports_are_removed() {
+        let repo_dir = std::env::temp_dir().join(format!("autocode-test-suite-{}", std::process::id()));
+        std::fs::create_dir_all(&repo_dir).unwrap();
+        let report = Path::new("junit.xml");
+        std::fs::write(repo_dir.join(report), "<testsuite><testcase name=\"old\"><failure message=\"stale\"/></testcase></testsuite>").unwrap();
+
+        let result = run_test_command("echo 'not ok 1 - flaky # TODO'", &repo_dir, Some(report), Duration::from_secs(10)).await.unwrap();
+        assert!(result.passed);
+        assert!(result.failures.is_empty());
+        assert!(!repo_dir.join(report).exists());
+
+        let result = run_test_command("exit 1", &repo_dir, Some(report), Duration::from_secs(10)).await.unwrap();
+        assert!(!result.passed);
+        std::fs::remove_dir_all(&repo_dir).unwrap();
+    }
+}
@@ -0,0 +1,171 @@
+use anyhow::{Context, Result}; // Importing Result and Context from anyhow crate
+use serde::{Deserialize, Serialize};
+use similar::TextDiff;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use crate::library::feature::Step;
+use crate::library::prompt::Message;
+use crate::library::syntax::code_language;
+use crate::library::verdict::Verdict;
+
+pub const TRANSCRIPT_FILE: &str = "transcript.json";
+pub const REPORT_FILE: &str = "report.md";
+
+// Everything that happened in a feature run, saved to its run directory
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Transcript {
+    pub feature: Option<String>,
+    pub started_at: String,
+    pub passed: bool,
+    pub duration_ms: u64,
+    pub steps: Vec<StepTranscript>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StepTranscript {
+    pub description: String,
+    pub target: String,
+    pub prompt: String,
+    pub attempts: Vec<Attempt>,
+    // Every turn sent to and received from the model, in order
+    #[serde(default)]
+    pub conversation: Vec<Message>,
+    pub passed: bool,
+    // Why the step stopped, when it failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    // Descriptions of the steps the planner put in this one's place, if it failed
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub replanned_into: Vec<String>,
+    pub duration_ms: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Attempt {
+    // The reply the code was extracted from
+    pub response: String,
+    pub extracted_code: String,
+    // Unified diff of the target file made by this attempt
+    pub diff: String,
+    pub console_logs: String,
+    pub build_errors: String,
+    // Syntax, typecheck and lint errors that stopped the page from being loaded
+    #[serde(default)]
+    pub static_errors: String,
+    // Summary of the testCommand run, when the step has one
+    #[serde(default)]
+    pub test_suite: String,
+    // The evaluator's reply on whether the logs show the step working
+    pub evaluation: String,
+    pub verdict: Option<Verdict>,
+    pub passed: bool,
+    pub duration_ms: u64,
+}
+
+impl Transcript {
+    pub fn new(feature: Option<String>) -> Self {
+        Transcript {
+            feature,
+            started_at: chrono::Local::now().to_rfc3339(),
+            ..Transcript::default()
+        }
+    }
+
+    // Writes transcript.json and report.md, returning the report's path
+    pub fn save(&self, run_dir: &Path) -> Result<PathBuf> {
+        fs::create_dir_all(run_dir)
+            .with_context(|| format!("Failed to create run directory {}", run_dir.display()))?;
+        let json = serde_json::to_string_pretty(self)?;
+        fs::write(run_dir.join(TRANSCRIPT_FILE), json)
+            .with_context(|| format!("Failed to write {}", TRANSCRIPT_FILE))?;
+        let report_path = run_dir.join(REPORT_FILE);
+        fs::write(&report_path, self.to_markdown())
+            .with_context(|| format!("Failed to write {}", REPORT_FILE))?;
+        Ok(report_path)
+    }
+
+    pub fn to_markdown(&self) -> String {
+        let mut report = format!("# {}\n\n", self.feature.as_deref().unwrap_or("Feature run"));
+        report += &format!("Started {}. {} after {}.\n", self.started_at, if self.passed { "Passed" } else { "Failed" }, seconds(self.duration_ms));
+        for (i, step) in self.steps.iter().enumerate() {
+            report += &format!("\n## Step {}: {}\n\n", i + 1, step.description);
+            report += &format!("Target `{}`. {} after {} attempt(s) in {}.\n", step.target, if step.passed { "Passed" } else { "Failed" }, step.attempts.len(), seconds(step.duration_ms));
+            if let Some(error) = &step.error {
+                report += &format!("\n**Error:** {}\n", error);
+            }
+            if !step.replanned_into.is_empty() {
+                report += &format!("\nReplanned into {} step(s):\n", step.replanned_into.len());
+                for description in &step.replanned_into {
+                    report += &format!("- {}\n", description);
+                }
+            }
+            report += &format!("\n### Prompt\n\n{}\n", fenced(&step.prompt, ""));
+            for (j, attempt) in step.attempts.iter().enumerate() {
+                report += &format!("\n### Attempt {} ({}, {})\n", j + 1, if attempt.passed { "passed" } else { "failed" }, seconds(attempt.duration_ms));
+                report += &format!("\n#### Response\n\n{}\n", fenced(&attempt.response, ""));
+                report += &format!("\n#### Extracted code\n\n{}\n", fenced(&attempt.extracted_code, code_language(&step.target)));
+                report += &format!("\n#### Diff\n\n{}\n", fenced(&attempt.diff, "diff"));
+                report += &format!("\n#### Console logs\n\n{}\n", fenced(&attempt.console_logs, ""));
+                if !attempt.static_errors.is_empty() {
+                    report += &format!("\n#### Static checks\n\n{}\n", fenced(&attempt.static_errors, ""));
+                }
+                if !attempt.build_errors.is_empty() {
+                    report += &format!("\n#### Build errors\n\n{}\n", fenced(&attempt.build_errors, ""));
+                }
+                if !attempt.test_suite.is_empty() {
+                    report += &format!("\n#### Test suite\n\n{}\n", fenced(&attempt.test_suite, ""));
+                }
+                report += &format!("\n#### Evaluation\n\n{}\n", fenced(&attempt.evaluation, ""));
+                if let Some(verdict) = &attempt.verdict {
+                    report += &format!("\n**Verdict:** {}. {}\n", if verdict.passed { "passed" } else { "failed" }, verdict.reason);
+                }
+            }
+        }
+        report
+    }
+}
+
+impl StepTranscript {
+    pub fn new(step: &Step) -> Self {
+        StepTranscript {
+            description: step.description.clone(),
+            target: step.target.clone(),
+            ..StepTranscript::default()
+        }
+    }
+}
+
+// Unified diff of one file, empty when nothing changed
+pub fn file_diff(file_name: &str, before: &str, after: &str) -> String {
+    if before == after {
+        return String::new();
+    }
+    TextDiff::from_lines(before, after)
+        .unified_diff()
+        .header(&format!("a/{}", file_name), &format!("b/{}", file_name))
+        .to_string()
+}
+
+pub fn elapsed_ms(start: Instant) -> u64 {
+    start.elapsed().as_millis() as u64
+}
+
+fn seconds(ms: u64) -> String {
+    format!("{:.1}s", ms as f64 / 1000.0)
+}
+
+// A code block whose fence can't be closed early by backticks in the text
+fn fenced(text: &str, language: &str) -> String {
+    if text.is_empty() {
+        return "_(empty)_".to_string();
+    }
+    let mut fence = "```".to_string();
+    while text.contains(&fence) {
+        fence.push('`');
+    }
+    format!("{}{}\n{}\n{}", fence, language, text.trim_end(), fence)
+}
@@ -0,0 +1,449 @@
+use serde_json::{Map, Value};
+use anyhow::{Context, Result}; // Importing Result and Context from anyhow crate
+use std::fmt;
+use std::fs;
+use std::path::{Component, Path};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub pointer: String,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let pointer = if self.pointer.is_empty() { "/" } else { &self.pointer };
+        write!(f, "{}: {}", pointer, self.message)
+    }
+}
+
+pub fn validate_feature_file(path: &str) -> Result<Vec<Diagnostic>> {
+    let feature_data_str = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read file: {}", path))?;
+    let feature_data: Value = serde_json::from_str(&feature_data_str)
+        .with_context(|| format!("Failed to deserialize JSON data from file: {}", path))?;
+    Ok(validate_feature(&feature_data))
+}
+
+// Checks a raw feature spec, collecting every problem instead of stopping at the first
+pub fn validate_feature(feature_data: &Value) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    let Some(feature) = feature_data.as_object() else {
+        push(&mut diagnostics, "", "feature spec must be a JSON object");
+        return diagnostics;
+    };
+
+    for key in ["docId", "repoURL", "dotenvContents", "autocodeDotenv", "serviceJSON", "description", "status"] {
+        if let Some(value) = feature.get(key) {
+            if !value.is_string() {
+                push(&mut diagnostics, &format!("/{}", key), "must be a string");
+            }
+        }
+    }
+
+    if let Some(llm) = feature.get("llm") {
+        validate_llm(llm, "/llm", &mut diagnostics);
+    }
+    validate_test_command(feature, "", &mut diagnostics);
+
+    match feature.get("steps") {
+        None => push(&mut diagnostics, "/steps", "missing required field"),
+        Some(Value::Array(steps)) => {
+            if steps.is_empty() {
+                push(&mut diagnostics, "/steps", "must contain at least one step");
+            }
+            for (i, step) in steps.iter().enumerate() {
+                validate_step(step, &format!("/steps/{}", i), &mut diagnostics);
+            }
+        }
+        Some(_) => push(&mut diagnostics, "/steps", "must be an array"),
+    }
+
+    diagnostics
+}
+
+fn validate_step(step: &Value, pointer: &str, diagnostics: &mut Vec<Diagnostic>) {
+    let Some(step) = step.as_object() else {
+        push(diagnostics, pointer, "step must be a JSON object");
+        return;
+    };
+
+    require_string(step.get("description"), &format!("{}/description", pointer), diagnostics);
+    let target = require_string(step.get("target"), &format!("{}/target", pointer), diagnostics);
+
+    let files_pointer = format!("{}/files", pointer);
+    let mut target_names = Vec::new();
+    let mut file_names = Vec::new();
+    let mut files_listed = false;
+    match step.get("files") {
+        None => {}
+        Some(Value::Array(files)) => {
+            files_listed = !files.is_empty();
+            for (i, file) in files.iter().enumerate() {
+                let file_pointer = format!("{}/{}", files_pointer, i);
+                let Some(file) = file.as_object() else {
+                    push(diagnostics, &file_pointer, "file must be a JSON object");
+                    continue;
+                };
+                let file_name = require_string(file.get("fileName"), &format!("{}/fileName", file_pointer), diagnostics);
+                if let Some(file_path) = require_string(file.get("filePath"), &format!("{}/filePath", file_pointer), diagnostics) {
+                    if let Some(problem) = check_relative_path(file_path) {
+                        push(diagnostics, &format!("{}/filePath", file_pointer), &problem);
+                    }
+                }
+                if let Some(contents) = file.get("fileContents") {
+                    if !contents.is_string() {
+                        push(diagnostics, &format!("{}/fileContents", file_pointer), "must be a string");
+                    }
+                }
+                let is_target = match file.get("isTarget") {
+                    None => false,
+                    Some(Value::Bool(is_target)) => *is_target,
+                    Some(_) => {
+                        push(diagnostics, &format!("{}/isTarget", file_pointer), "must be a boolean");
+                        false
+                    }
+                };
+                if let Some(file_name) = file_name {
+                    file_names.push(file_name);
+                    if is_target {
+                        target_names.push(file_name);
+                    }
+                } else if is_target {
+                    target_names.push("");
+                }
+            }
+            if target_names.len() > 1 {
+                push(diagnostics, &files_pointer, &format!("at most one file may have \"isTarget\": true, found {}", target_names.len()));
+            }
+        }
+        Some(_) => push(diagnostics, &files_pointer, "must be an array"),
+    }
+
+    if let Some(target) = target {
+        if target_names.is_empty() && !files_listed {
+            // With no files listed, the target is a path in the repo
+            if let Some(problem) = check_relative_path(target) {
+                push(diagnostics, &format!("{}/target", pointer), &problem);
+            }
+        } else if !file_names.contains(&target) {
+            push(diagnostics, &format!("{}/target", pointer), &format!("\"{}\" does not match the fileName of any file in this step", target));
+        } else if target_names.len() == 1 && !target_names[0].is_empty() && target_names[0] != target {
+            push(diagnostics, &format!("{}/target", pointer), &format!("\"{}\" does not match the target file \"{}\"", target, target_names[0]));
+        }
+    }
+
+    if let Some(test_path) = step.get("testPath") {
+        let test_path_pointer = format!("{}/testPath", pointer);
+        match test_path.as_str() {
+            Some(test_path) => match url::Url::parse(test_path) {
+                Ok(url) if url.scheme() == "http" || url.scheme() == "https" => {}
+                Ok(url) => push(diagnostics, &test_path_pointer, &format!("URL scheme must be http or https, found \"{}\"", url.scheme())),
+                Err(err) => push(diagnostics, &test_path_pointer, &format!("\"{}\" is not a valid URL: {}", test_path, err)),
+            },
+            None => push(diagnostics, &test_path_pointer, "must be a string"),
+        }
+    }
+
+    if let Some(llm) = step.get("llm") {
+        validate_llm(llm, &format!("{}/llm", pointer), diagnostics);
+    }
+
+    if let Some(max_attempts) = step.get("maxAttempts") {
+        if max_attempts.as_u64().unwrap_or(0) == 0 {
+            push(diagnostics, &format!("{}/maxAttempts", pointer), &format!("must be a positive integer, found {}", max_attempts));
+        }
+    }
+
+    if let Some(repair_strategy) = step.get("repairStrategy") {
+        match repair_strategy.as_str() {
+            Some("conversation" | "fresh" | "evaluator") => {}
+            _ => push(diagnostics, &format!("{}/repairStrategy", pointer), &format!("must be \"conversation\", \"fresh\" or \"evaluator\", found {}", repair_strategy)),
+        }
+    }
+
+    validate_test_command(step, pointer, diagnostics);
+
+    if let Some(expect) = step.get("expect") {
+        validate_expect(expect, &format!("{}/expect", pointer), diagnostics);
+    }
+
+    if let Some(show_html) = step.get("showHTML") {
+        let boolean_like = match show_html {
+            Value::Bool(_) => true,
+            Value::String(value) => matches!(value.to_lowercase().as_str(), "true" | "false"),
+            _ => false,
+        };
+        if !boolean_like {
+            push(diagnostics, &format!("{}/showHTML", pointer), &format!("must be true, false, \"true\" or \"false\", found {}", show_html));
+        }
+    }
+}
+
+fn validate_test_command(object: &Map<String, Value>, pointer: &str, diagnostics: &mut Vec<Diagnostic>) {
+    for key in ["testCommand", "typecheckCommand", "lintCommand"] {
+        if let Some(command) = object.get(key) {
+            if command.as_str().is_none_or(|command| command.trim().is_empty()) {
+                push(diagnostics, &format!("{}/{}", pointer, key), "must be a non-empty string");
+            }
+        }
+    }
+    if let Some(junit_report) = object.get("junitReport") {
+        let junit_pointer = format!("{}/junitReport", pointer);
+        match junit_report.as_str() {
+            Some(junit_report) => {
+                if let Some(problem) = check_relative_path(junit_report) {
+                    push(diagnostics, &junit_pointer, &problem);
+                }
+            }
+            None => push(diagnostics, &junit_pointer, "must be a string"),
+        }
+    }
+}
+
+fn validate_expect(expect: &Value, pointer: &str, diagnostics: &mut Vec<Diagnostic>) {
+    let Some(expect) = expect.as_object() else {
+        push(diagnostics, pointer, "must be a JSON object");
+        return;
+    };
+    for (key, value) in expect {
+        let key_pointer = format!("{}/{}", pointer, escape_pointer_token(key));
+        match key.as_str() {
+            "logs" | "forbiddenLogs" => {
+                let Some(patterns) = value.as_array() else {
+                    push(diagnostics, &key_pointer, "must be an array of regular expressions");
+                    continue;
+                };
+                for (i, pattern) in patterns.iter().enumerate() {
+                    let pattern_pointer = format!("{}/{}", key_pointer, i);
+                    match pattern.as_str().map(regex::Regex::new) {
+                        Some(Ok(_)) => {}
+                        Some(Err(err)) => push(diagnostics, &pattern_pointer, &format!("is not a valid regular expression: {}", err)),
+                        None => push(diagnostics, &pattern_pointer, "must be a string"),
+                    }
+                }
+            }
+            "selectors" => {
+                let Some(selectors) = value.as_array() else {
+                    push(diagnostics, &key_pointer, "must be an array of {\"selector\", \"text\"} objects");
+                    continue;
+                };
+                for (i, selector) in selectors.iter().enumerate() {
+                    let selector_pointer = format!("{}/{}", key_pointer, i);
+                    match selector.get("selector").and_then(Value::as_str) {
+                        Some(css) => {
+                            if let Err(err) = scraper::Selector::parse(css) {
+                                push(diagnostics, &format!("{}/selector", selector_pointer), &format!("is not a valid CSS selector: {}", err));
+                            }
+                        }
+                        None => push(diagnostics, &format!("{}/selector", selector_pointer), "must be a string"),
+                    }
+                    if selector.get("text").is_some_and(|text| !text.is_string()) {
+                        push(diagnostics, &format!("{}/text", selector_pointer), "must be a string");
+                    }
+                }
+            }
+            "noPageErrors" | "evaluate" => {
+                if !value.is_boolean() {
+                    push(diagnostics, &key_pointer, &format!("must be true or false, found {}", value));
+                }
+            }
+            _ => push(diagnostics, &key_pointer, "unknown expectation"),
+        }
+    }
+}
+
+fn validate_llm(llm: &Value, pointer: &str, diagnostics: &mut Vec<Diagnostic>) {
+    let Some(llm) = llm.as_object() else {
+        push(diagnostics, pointer, "must be a JSON object");
+        return;
+    };
+    for (key, value) in llm {
+        let key_pointer = format!("{}/{}", pointer, escape_pointer_token(key));
+        match key.as_str() {
+            "provider" => match value.as_str() {
+                Some("openai" | "anthropic" | "mock") => {}
+                _ => push(diagnostics, &key_pointer, &format!("must be \"openai\", \"anthropic\" or \"mock\", found {}", value)),
+            },
+            "model" | "apiKeyEnv" => {
+                if !value.is_string() {
+                    push(diagnostics, &key_pointer, "must be a string");
+                }
+            }
+            "temperature" => match value.as_f64() {
+                Some(temperature) if (0.0..=2.0).contains(&temperature) => {}
+                _ => push(diagnostics, &key_pointer, &format!("must be a number from 0 to 2, found {}", value)),
+            },
+            "maxTokens" | "contextWindow" => {
+                if value.as_u64().unwrap_or(0) == 0 {
+                    push(diagnostics, &key_pointer, &format!("must be a positive integer, found {}", value));
+                }
+            }
+            "baseURL" => match value.as_str().map(url::Url::parse) {
+                Some(Ok(url)) if url.scheme() == "http" || url.scheme() == "https" => {}
+                _ => push(diagnostics, &key_pointer, &format!("must be an http or https URL, found {}", value)),
+            },
+            "responses" => {
+                if !value.as_array().is_some_and(|responses| responses.iter().all(Value::is_string)) {
+                    push(diagnostics, &key_pointer, "must be an array of strings");
+                }
+            }
+            _ => push(diagnostics, &key_pointer, "unknown llm setting"),
+        }
+    }
+}
+
+// Returns a description of the problem if the path is absolute or climbs out of the repo
+fn check_relative_path(file_path: &str) -> Option<String> {
+    let path = Path::new(file_path);
+    if file_path.is_empty() {
+        return Some("must not be empty".to_string());
+    }
+    if path.is_absolute() || file_path.starts_with('/') || file_path.starts_with('\\') {
+        return Some(format!("\"{}\" must be relative to the repository root", file_path));
+    }
+    let mut depth: i32 = 0;
+    for component in path.components() {
+        match component {
+            Component::ParentDir => depth -= 1,
+            Component::Normal(_) => depth += 1,
+            Component::CurDir => {}
+            Component::RootDir | Component::Prefix(_) => {
+                return Some(format!("\"{}\" must be relative to the repository root", file_path));
+            }
+        }
+        if depth < 0 {
+            return Some(format!("\"{}\" escapes the repository root", file_path));
+        }
+    }
+    None
+}
+
+// A key as a JSON pointer token, per RFC 6901
+fn escape_pointer_token(key: &str) -> String {
+    key.replace('~', "~0").replace('/', "~1")
+}
+
+fn require_string<'a>(value: Option<&'a Value>, pointer: &str, diagnostics: &mut Vec<Diagnostic>) -> Option<&'a str> {
+    match value {
+        None => {
+            push(diagnostics, pointer, "missing required field");
+            None
+        }
+        Some(Value::String(value)) => Some(value),
+        Some(_) => {
+            push(diagnostics, pointer, "must be a string");
+            None
+        }
+    }
+}
+
+fn push(diagnostics: &mut Vec<Diagnostic>, pointer: &str, message: &str) {
+    diagnostics.push(Diagnostic { pointer: pointer.to_string(), message: message.to_string() });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    // The diagnostics for a feature with one step, as "pointer: message"
+    fn step_diagnostics(step: Value) -> Vec<String> {
+        validate_feature(&json!({ "steps": [step] })).iter().map(Diagnostic::to_string).collect()
+    }
+
+    fn file(file_name: &str, file_path: &str) -> Value {
+        json!({ "fileName": file_name, "filePath": file_path })
+    }
+
+    #[test]
+    fn valid_step_has_no_diagnostics() {
+        let step = json!({ "description": "d", "target": "page.js", "files": [file("page.js", "app/page.js")], "testPath": "http://localhost:3000/", "showHTML": "false" });
+        assert!(step_diagnostics(step).is_empty());
+    }
+
+    #[test]
+    fn at_most_one_file_is_the_target() {
+        let mut first = file("page.js", "app/page.js");
+        first["isTarget"] = json!(true);
+        let mut second = file("layout.js", "app/layout.js");
+        second["isTarget"] = json!("yes");
+        let mut third = file("footer.js", "app/footer.js");
+        third["isTarget"] = json!(true);
+        assert_eq!(
+            step_diagnostics(json!({ "description": "d", "target": "page.js", "files": [first, second, third] })),
+            ["/steps/0/files/1/isTarget: must be a boolean", "/steps/0/files: at most one file may have \"isTarget\": true, found 2"]
+        );
+    }
+
+    #[test]
+    fn target_must_match_the_target_file() {
+        let mut target = file("page.js", "app/page.js");
+        target["isTarget"] = json!(true);
+        let files = json!([target, file("layout.js", "app/layout.js")]);
+        assert_eq!(
+            step_diagnostics(json!({ "description": "d", "target": "layout.js", "files": files })),
+            ["/steps/0/target: \"layout.js\" does not match the target file \"page.js\""]
+        );
+    }
+
+    #[test]
+    fn test_path_must_be_an_http_url() {
+        assert_eq!(
+            step_diagnostics(json!({ "description": "d", "target": "page.js", "testPath": "ftp://localhost/" })),
+            ["/steps/0/testPath: URL scheme must be http or https, found \"ftp\""]
+        );
+        assert_eq!(
+            step_diagnostics(json!({ "description": "d", "target": "page.js", "testPath": "localhost:3000" })),
+            ["/steps/0/testPath: URL scheme must be http or https, found \"localhost\""]
+        );
+        assert_eq!(
+            step_diagnostics(json!({ "description": "d", "target": "page.js", "testPath": "/about" })),
+            ["/steps/0/testPath: \"/about\" is not a valid URL: relative URL without a base"]
+        );
+    }
+
+    #[test]
+    fn show_html_must_be_boolean_like() {
+        assert!(step_diagnostics(json!({ "description": "d", "target": "page.js", "showHTML": true })).is_empty());
+        assert_eq!(
+            step_diagnostics(json!({ "description": "d", "target": "page.js", "showHTML": "yes" })),
+            ["/steps/0/showHTML: must be true, false, \"true\" or \"false\", found \"yes\""]
+        );
+    }
+
+    #[test]
+    fn file_paths_stay_in_the_repo() {
+        let files = json!([file("page.js", "/etc/page.js"), file("layout.js", "app/../../layout.js"), file("footer.js", "app/../footer.js")]);
+        assert_eq!(
+            step_diagnostics(json!({ "description": "d", "target": "page.js", "files": files })),
+            ["/steps/0/files/0/filePath: \"/etc/page.js\" must be relative to the repository root", "/steps/0/files/1/filePath: \"app/../../layout.js\" escapes the repository root"]
+        );
+    }
+
+    #[test]
+    fn keys_are_escaped_in_pointers() {
+        let diagnostics: Vec<String> = validate_feature(&json!({
+            "llm": { "base/url": "x" },
+            "steps": [{ "description": "d", "target": "page.js", "expect": { "logs~": [] } }]
+        })).iter().map(Diagnostic::to_string).collect();
+        assert_eq!(diagnostics, ["/llm/base~1url: unknown llm setting", "/steps/0/expect/logs~0: unknown expectation"]);
+    }
+
+    #[test]
+    fn target_is_a_repo_path_when_no_files_are_listed() {
+        assert!(step_diagnostics(json!({ "description": "d", "target": "app/page.js" })).is_empty());
+        assert!(step_diagnostics(json!({ "description": "d", "target": "app/page.js", "files": [] })).is_empty());
+        assert_eq!(step_diagnostics(json!({ "description": "d", "target": "../page.js" })), ["/steps/0/target: \"../page.js\" escapes the repository root"]);
+    }
+
+    #[test]
+    fn listed_files_must_include_the_target() {
+        let file = json!({ "fileName": "page.js", "filePath": "app/page.js" });
+        assert!(step_diagnostics(json!({ "description": "d", "target": "page.js", "files": [file] })).is_empty());
+        assert_eq!(
+            step_diagnostics(json!({ "description": "d", "target": "other.js", "files": [file] })),
+            ["/steps/0/target: \"other.js\" does not match the fileName of any file in this step"]
+        );
+    }
+}
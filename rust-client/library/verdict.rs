@@ -0,0 +1,138 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+// What the evaluator decided about an attempt. Extra fields a model adds are
+// ignored, but `passed` and `reason` must be there with the right types.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Verdict {
+    pub passed: bool,
+    pub reason: String,
+    // The whole corrected target file, when the attempt failed
+    #[serde(default)]
+    pub corrected_code: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Evaluation {
+    Verdict(Verdict),
+    // A reply that says neither yes nor no in a way that can be trusted
+    Ambiguous(String),
+}
+
+// JSON schema of a verdict, for JSON mode and tool calls
+pub fn verdict_schema() -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "passed": { "type": "boolean", "description": "Whether the code did exactly what the user wanted" },
+            "reason": { "type": "string", "description": "Why, citing the logs" },
+            "corrected_code": { "type": ["string", "null"], "description": "The whole corrected file without Markdown fences, or null if it passed" }
+        },
+        "required": ["passed", "reason", "corrected_code"],
+        "additionalProperties": false
+    })
+}
+
+// Reads the evaluator's reply: strictly as a verdict object first, then as a
+// verdict object inside other text, then by a yes or no that opens the reply.
+// Anything else is ambiguous rather than a guess.
+pub fn parse_verdict(reply: &str) -> Evaluation {
+    if let Some(verdict) = parse_strict(reply) {
+        return Evaluation::Verdict(verdict);
+    }
+    if let Some(verdict) = parse_embedded(reply) {
+        return Evaluation::Verdict(verdict);
+    }
+    match leading_answer(reply) {
+        Some(passed) => Evaluation::Verdict(Verdict {
+            passed,
+            reason: reply.trim().to_string(),
+            corrected_code: None,
+        }),
+        None => Evaluation::Ambiguous(reply.to_string()),
+    }
+}
+
+// The reply is exactly one verdict object, optionally in a ```json fence
+fn parse_strict(reply: &str) -> Option<Verdict> {
+    let mut text = reply.trim();
+    if let Some(fenced) = text.strip_prefix("```") {
+        let fenced = fenced.strip_prefix("json").unwrap_or(fenced);
+        text = fenced.strip_suffix("```")?.trim();
+    }
+    serde_json::from_str::<Verdict>(text).ok().map(normalize)
+}
+
+// The first `{` to the last `}` of a chatty reply
+fn parse_embedded(reply: &str) -> Option<Verdict> {
+    let start = reply.find('{')?;
+    let end = reply.rfind('}')?;
+    if end <= start {
+        return None;
+    }
+    serde_json::from_str::<Verdict>(&reply[start..=end]).ok().map(normalize)
+}
+
+// "Yes, ..." or "No. ..." at the very start; "yes" further in means nothing
+fn leading_answer(reply: &str) -> Option<bool> {
+    let first_word: String = reply.trim_start()
+        .trim_start_matches(|c: char| !c.is_alphanumeric())
+        .chars()
+        .take_while(|c| c.is_alphanumeric())
+        .collect::<String>()
+        .to_lowercase();
+    match first_word.as_str() {
+        "yes" => Some(true),
+        "no" => Some(false),
+        _ => None,
+    }
+}
+
+fn normalize(mut verdict: Verdict) -> Verdict {
+    verdict.corrected_code = verdict.corrected_code
+        .map(|code| code.trim().to_string())
+        .filter(|code| !code.is_empty() && !verdict.passed);
+    verdict
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn verdict(passed: bool, reason: &str, corrected_code: Option<&str>) -> Evaluation {
+        Evaluation::Verdict(Verdict { passed, reason: reason.to_string(), corrected_code: corrected_code.map(str::to_string) })
+    }
+
+    #[test]
+    fn reads_a_verdict_object() {
+        assert_eq!(parse_verdict(r#"{"passed": true, "reason": "count is 1 was logged", "corrected_code": null}"#), verdict(true, "count is 1 was logged", None));
+        assert_eq!(parse_verdict("```json\n{\"passed\": false, \"reason\": \"no log\", \"corrected_code\": \" fixed() \"}\n```"), verdict(false, "no log", Some("fixed()")));
+    }
+
+    #[test]
+    fn ignores_extra_fields_but_not_missing_ones() {
+        assert_eq!(parse_verdict(r#"{"passed": true, "reason": "ok", "confidence": 0.9}"#), verdict(true, "ok", None));
+        assert!(matches!(parse_verdict(r#"{"passed": true}"#), Evaluation::Ambiguous(_)));
+        assert!(matches!(parse_verdict(r#"{"passed": "yes", "reason": "ok"}"#), Evaluation::Ambiguous(_)));
+    }
+
+    #[test]
+    fn finds_a_verdict_inside_prose() {
+        let reply = "Here is my verdict: {\"passed\": false, \"reason\": \"nothing was logged\"} Hope that helps.";
+        assert_eq!(parse_verdict(reply), verdict(false, "nothing was logged", None));
+    }
+
+    #[test]
+    fn drops_corrected_code_from_a_pass() {
+        assert_eq!(parse_verdict(r#"{"passed": true, "reason": "ok", "corrected_code": "x()"}"#), verdict(true, "ok", None));
+    }
+
+    #[test]
+    fn only_a_leading_yes_or_no_counts() {
+        let reply = "No, yesterday's value is still shown, so yes, it needs another try.";
+        assert_eq!(parse_verdict(reply), verdict(false, reply, None));
+        assert_eq!(parse_verdict("Yes. The count is logged."), verdict(true, "Yes. The count is logged.", None));
+        assert!(matches!(parse_verdict("Yesterday's value is shown."), Evaluation::Ambiguous(_)));
+        assert!(matches!(parse_verdict("It looks like yes, mostly."), Evaluation::Ambiguous(_)));
+    }
+}
@@ -0,0 +1,275 @@
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use anyhow::Result;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use crate::library::cassette::{Cassette, CassetteMode};
+use crate::library::feature::{LlmConfig, ProviderKind, RepairStrategy};
+use crate::library::log_and_run::BrowserOptions;
+use crate::library::service_client::{ServiceClient, ServiceConfig};
+
+/// Adds features to React apps one tested step at a time
+#[derive(Debug, Parser)]
+#[command(name = "autocode-native", version)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Commands,
+
+    /// Use an existing checkout of the target repo instead of cloning repoURL
+    #[arg(long, global = true, value_name = "DIR")]
+    pub repo_dir: Option<PathBuf>,
+
+    /// Start the repo's dev server with `npm run dev` (always done when cloning)
+    #[arg(long, global = true)]
+    pub serve: bool,
+
+    /// Debugging attempts per step before giving up, unless a step sets maxAttempts
+    #[arg(long, global = true, default_value_t = 3, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    pub max_attempts: usize,
+
+    /// How failed attempts are retried, unless a step sets repairStrategy
+    #[arg(long, global = true, value_enum, default_value_t = RepairStrategy::Conversation)]
+    pub repair_strategy: RepairStrategy,
+
+    /// Default LLM provider for steps whose spec doesn't choose one [default: openai]
+    #[arg(long, global = true, value_enum)]
+    pub provider: Option<ProviderKind>,
+
+    /// Default chat model for steps whose spec doesn't choose one [default: gpt-3.5-turbo]
+    #[arg(long, global = true)]
+    pub model: Option<String>,
+
+    /// Where code is extracted from LLM replies
+    #[arg(long, global = true, value_enum, default_value_t = Backend::Native)]
+    pub extractor: Backend,
+
+    /// How partial replies are spliced into the target file
+    #[arg(long, global = true, value_enum, default_value_t = Backend::Native)]
+    pub splicer: Backend,
+
+    /// What loads each step's testPath and collects its logs
+    #[arg(long, global = true, value_enum, default_value_t = Backend::Native)]
+    pub runner: Backend,
+
+    /// Seconds a test page may take before the run is abandoned
+    #[arg(long, global = true, value_name = "SECS", default_value_t = 60)]
+    pub browser_timeout: u64,
+
+    /// Seconds to wait for the dev server to serve a page after starting or after a write
+    #[arg(long, global = true, value_name = "SECS", default_value_t = 60)]
+    pub ready_timeout: u64,
+
+    /// Seconds a testCommand, typecheck or lint may run before it is stopped and counted as failing
+    #[arg(long, global = true, value_name = "SECS", default_value_t = 300)]
+    pub test_timeout: u64,
+
+    /// Typecheck run after every write unless the spec sets typecheckCommand, e.g. "npx tsc --noEmit"
+    #[arg(long, global = true, value_name = "CMD")]
+    pub typecheck_command: Option<String>,
+
+    /// Linter run after every write unless the spec sets lintCommand; {file} is replaced with the target's path in the repo
+    #[arg(long, global = true, value_name = "CMD")]
+    pub lint_command: Option<String>,
+
+    /// Tokens of files found through the target's imports to add to each prompt; 0 turns this off
+    #[arg(long, global = true, value_name = "TOKENS", default_value_t = 4000)]
+    pub context_tokens: usize,
+
+    /// Times a run may ask the planner to replace a step that used up its attempts; 0 turns this off
+    #[arg(long, global = true, value_name = "N", default_value_t = 2)]
+    pub replan_budget: usize,
+
+    /// express-autocode-api base URL [default: AUTOCODE_API_URL, else localhost on PORT, else http://localhost:4000]
+    #[arg(long, global = true, value_name = "URL")]
+    pub api_url: Option<String>,
+
+    /// Seconds an express-autocode-api request may take, including a log-and-run
+    #[arg(long, global = true, value_name = "SECS", default_value_t = 300)]
+    pub api_timeout: u64,
+
+    /// Chrome or Chromium binary for the native runner [default: found on PATH]
+    #[arg(long, global = true, value_name = "PATH")]
+    pub chrome_path: Option<PathBuf>,
+
+    /// Save every model and service call to DIR, to replay the run later
+    #[arg(long, global = true, value_name = "DIR", conflicts_with_all = ["replay", "replay_or_record"])]
+    pub record: Option<PathBuf>,
+
+    /// Answer every model and service call from DIR, failing on any that wasn't recorded
+    #[arg(long, global = true, value_name = "DIR", conflicts_with = "replay_or_record")]
+    pub replay: Option<PathBuf>,
+
+    /// Answer the calls recorded in DIR from it, and make and record the rest
+    #[arg(long, global = true, value_name = "DIR")]
+    pub replay_or_record: Option<PathBuf>,
+
+    /// Where each run's transcript.json and report.md are written, in a timestamped directory
+    #[arg(long, global = true, value_name = "DIR", default_value = "runs")]
+    pub runs_dir: PathBuf,
+
+    /// Verbosity of progress output
+    #[arg(long, global = true, value_enum, default_value_t = LogLevel::Info)]
+    pub log_level: LogLevel,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Commands {
+    /// Execute every step of a feature spec
+    Run(RunArgs),
+    /// Check a feature spec without calling the model or cloning anything
+    Validate {
+        #[arg(default_value = "feature.json")]
+        spec: String,
+    },
+    /// Generate the steps of a feature spec from its description
+    Plan(PlanArgs),
+    /// Re-run a feature offline from a recorded session, like `run --replay`
+    Replay(ReplayArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct PlanArgs {
+    /// Feature spec with the description to plan
+    #[arg(default_value = "feature.json")]
+    pub spec: String,
+
+    /// Where to write the planned spec; defaults to the spec itself
+    #[arg(long, short, value_name = "PATH")]
+    pub output: Option<PathBuf>,
+
+    /// Replace the steps the spec already has
+    #[arg(long)]
+    pub force: bool,
+
+    /// Where the app is served, for the steps' test paths
+    #[arg(long, value_name = "URL", default_value = "http://localhost:3000")]
+    pub base_url: String,
+}
+
+#[derive(Debug, Args)]
+pub struct ReplayArgs {
+    #[command(flatten)]
+    pub run: RunArgs,
+
+    /// Directory the session was recorded to with --record or --replay-or-record
+    #[arg(long, value_name = "DIR", default_value = "cassette")]
+    pub cassette: PathBuf,
+}
+
+#[derive(Debug, Args)]
+pub struct RunArgs {
+    /// Feature spec to run (Local JSON mode). Specs with a docId are removed
+    /// from the API once every step passes (Remote JSON mode)
+    #[arg(default_value = "feature.json", conflicts_with = "from_api")]
+    pub spec: String,
+
+    /// Fetch the feature from the API instead of a file (API JSON mode)
+    #[arg(long)]
+    pub from_api: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Backend {
+    /// Do the work in-process
+    Native,
+    /// Call the matching express-autocode-api endpoint
+    Service,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl From<LogLevel> for log::LevelFilter {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Error => log::LevelFilter::Error,
+            LogLevel::Warn => log::LevelFilter::Warn,
+            LogLevel::Info => log::LevelFilter::Info,
+            LogLevel::Debug => log::LevelFilter::Debug,
+            LogLevel::Trace => log::LevelFilter::Trace,
+        }
+    }
+}
+
+// Settings that apply to every step of a run
+#[derive(Debug, Clone)]
+pub struct RunOptions {
+    pub max_attempts: usize,
+    pub repair_strategy: RepairStrategy,
+    pub extractor: Backend,
+    pub splicer: Backend,
+    pub runner: Backend,
+    pub browser: BrowserOptions,
+    pub ready_timeout: Duration,
+    pub test_timeout: Duration,
+    pub typecheck_command: Option<String>,
+    pub lint_command: Option<String>,
+    pub context_tokens: usize,
+    pub replan_budget: usize,
+    pub runs_dir: PathBuf,
+    // Shared by every call to express-autocode-api
+    pub service: ServiceClient,
+    // Where model and service calls are recorded or replayed from, if anywhere
+    pub cassette: Option<Arc<Cassette>>,
+    // Defaults that the feature and step llm settings are layered on
+    pub llm: LlmConfig,
+}
+
+impl Cli {
+    fn cassette(&self) -> Option<Cassette> {
+        match (&self.record, &self.replay, &self.replay_or_record) {
+            (Some(dir), _, _) => Some(Cassette::new(CassetteMode::Record, dir.clone())),
+            (_, Some(dir), _) => Some(Cassette::new(CassetteMode::Replay, dir.clone())),
+            (_, _, Some(dir)) => Some(Cassette::new(CassetteMode::ReplayOrRecord, dir.clone())),
+            _ => None,
+        }
+    }
+
+    pub fn run_options(&self) -> Result<RunOptions> {
+        let service = ServiceClient::new(&ServiceConfig::resolve(self.api_url.as_deref(), Duration::from_secs(self.api_timeout)))?;
+        Ok(RunOptions {
+            max_attempts: self.max_attempts,
+            repair_strategy: self.repair_strategy,
+            extractor: self.extractor,
+            splicer: self.splicer,
+            runner: self.runner,
+            browser: BrowserOptions {
+                timeout: Duration::from_secs(self.browser_timeout),
+                chrome_path: self.chrome_path.clone(),
+                ..BrowserOptions::default()
+            },
+            ready_timeout: Duration::from_secs(self.ready_timeout),
+            test_timeout: Duration::from_secs(self.test_timeout),
+            typecheck_command: self.typecheck_command.clone(),
+            lint_command: self.lint_command.clone(),
+            context_tokens: self.context_tokens,
+            replan_budget: self.replan_budget,
+            runs_dir: self.runs_dir.clone(),
+            service,
+            cassette: self.cassette().map(Arc::new),
+            llm: LlmConfig {
+                provider: self.provider,
+                model: self.model.clone(),
+                ..LlmConfig::default()
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn max_attempts_must_be_positive() {
+        assert_eq!(Cli::try_parse_from(["autocode-native", "--max-attempts", "2", "validate", "feature.json"]).unwrap().max_attempts, 2);
+        let err = Cli::try_parse_from(["autocode-native", "--max-attempts", "0", "validate", "feature.json"]).unwrap_err();
+        assert_eq!(err.kind(), clap::error::ErrorKind::ValueValidation);
+    }
+}
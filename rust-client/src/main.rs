@@ -1,102 +1,421 @@
-use serde_json::{json, Value};
-use std::path::{Path, PathBuf};
-use std::{clone, env, fs};
+use std::path::PathBuf;
+use std::{env, fs};
 use anyhow::{Context, Result}; // Importing Result and Context from anyhow crate
+use clap::Parser;
 use dotenvy::dotenv;
+use log::{debug, error, info, warn};
+mod cli;
+#[path = "../library/mod.rs"]
 mod library;
-use library::prompt::prompt;
-use library::extract_jsx::extract_jsx;
-use library::log_and_run::log_and_run;
+use cli::{Backend, Cli, Commands, PlanArgs, RunArgs, RunOptions};
+use library::prompt::{build_provider, LlmProvider, Message, Role};
+use library::verdict::{parse_verdict, verdict_schema, Evaluation, Verdict};
+use library::extract_jsx::{extract_jsx, extract_jsx_service};
+use library::log_and_run::{log_and_run, log_and_run_service, PageError, RunReport};
+use library::expect::check_expectations;
+use library::test_suite::{run_test_command, SuiteResult};
+use library::syntax::source_type_for;
+use library::context::discover_context;
+use library::prompt_budget::{assemble_prompt, PromptBudget};
+use library::cassette::{cassette_provider, recorded, Cassette, CassetteMode};
+use library::planner::{plan_feature, replan_step, repo_overview};
+use library::static_checks::{run_static_checks, syntax_problem, StaticCheck};
+use library::dev_server::{CompileEvent, CompileWatch, DevServer, DevServerConfig};
+use library::git::{commit_files, create_branch, feature_branch_name, has_uncommitted_changes, head_commit, is_git_repo, reset_to, summarize_range};
+use library::transcript::{elapsed_ms, file_diff, Attempt, StepTranscript, Transcript};
+use library::readiness::{wait_for_port, wait_until_served, Readiness};
 use library::get_feature::get_feature;
 use library::remove_feature::remove_feature;
-use library::get_updated_functions::get_updated_functions;
-use std::thread;
-use std::time::Duration;
-use std::fs::File;
+use library::get_updated_functions::{get_updated_functions, get_updated_functions_service};
+use library::feature::{feature_from_value, load_feature, Expectations, Feature, FileRef, LlmConfig, RepairStrategy, Step};
+use library::validate::{validate_feature, validate_feature_file};
+use serde_json::json;
 use std::io::Write;
+use std::sync::Arc;
+use std::time::Instant;
 use std::process::Command;
 
 #[tokio::main]
 async fn main() {
-    /* Local JSON mode and Remote JSON mode: let mut feature_data = json!({"feature": {...
-      Or uncomment the lines below to use json file */
-    let feature_data_str = fs::read_to_string("feature.json")
-      .with_context(|| format!("Failed to read file: {}", "feature.json"))
-      .unwrap();
-    let mut feature_data: Value = serde_json::from_str(&feature_data_str)
-      .with_context(|| format!("Failed to deserialize JSON data from file: {}", "feature.json"))
-      .unwrap();
-    let feature_data_immut: Value = serde_json::from_str(&feature_data_str)
-      .with_context(|| format!("Failed to deserialize JSON data from file: {}", "feature.json"))
-      .unwrap();
-
-    /* API JSON mode: uncomment the lines below, as well the remove
-    feature line further down */
-    /*let mut feature_data: Value = get_feature().await.unwrap();
-    println!("Feature data: {:?}", feature_data);*/
-    
-    /* Remote JSON mode and API JSON mode: uncomment the line below, as well the remove
+    let cli = Cli::parse();
+    env_logger::Builder::new()
+      .filter_level(cli.log_level.into())
+      .format_timestamp(None)
+      .init();
+
+    let exit_code = match &cli.command {
+      Commands::Run(args) => run_until_interrupted(args, &cli, None).await,
+      Commands::Validate { spec } => validate(spec),
+      Commands::Plan(args) => match plan(args, &cli).await {
+        Ok(()) => 0,
+        Err(err) => {
+          eprintln!("{:#}", err);
+          2
+        }
+      },
+      Commands::Replay(args) => run_until_interrupted(&args.run, &cli, Some(&args.cassette)).await,
+    };
+    std::process::exit(exit_code);
+}
+
+// Runs the feature, replaying it from `cassette` if given, and returns the
+// exit code. Dropping the run on Ctrl-C drops its dev servers, which kills them.
+async fn run_until_interrupted(args: &RunArgs, cli: &Cli, cassette: Option<&PathBuf>) -> i32 {
+    let mut options = match cli.run_options() {
+      Ok(options) => options,
+      Err(err) => {
+        eprintln!("{:#}", err);
+        return 2;
+      }
+    };
+    if let Some(cassette) = cassette {
+      options.cassette = Some(Arc::new(Cassette::new(CassetteMode::Replay, cassette.clone())));
+    }
+    tokio::select! {
+      result = run(args, cli, options) => match result {
+        Ok(true) => 0,
+        Ok(false) => 1,
+        Err(err) => {
+          eprintln!("{:#}", err);
+          2
+        }
+      },
+      _ = tokio::signal::ctrl_c() => {
+        eprintln!("Interrupted.");
+        130
+      }
+    }
+}
+
+// Runs every step of the feature, returning whether they all passed
+async fn run(args: &RunArgs, cli: &Cli, options: RunOptions) -> Result<bool> {
+    let run_start = Instant::now();
+    // Resolved now, since cloning changes the current directory
+    let run_dir = env::current_dir()?.join(&options.runs_dir).join(chrono::Local::now().format("%Y%m%d-%H%M%S").to_string());
+    let mut feature = if args.from_api {
+      // API JSON mode
+      let feature_data: serde_json::Value = recorded(options.cassette.as_deref(), "get-feature", json!({}), || get_feature(&options.service)).await?;
+      let diagnostics = validate_feature(&feature_data);
+      if !diagnostics.is_empty() {
+        for diagnostic in &diagnostics {
+          eprintln!("api feature: {}", diagnostic);
+        }
+        anyhow::bail!("{} problem(s) found in the feature from the API.", diagnostics.len());
+      }
+      feature_from_value(feature_data)?
+    } else {
+      // Local JSON mode and Remote JSON mode
+      let diagnostics = validate_feature_file(&args.spec)?;
+      if !diagnostics.is_empty() {
+        for diagnostic in &diagnostics {
+          eprintln!("{}: {}", args.spec, diagnostic);
+        }
+        anyhow::bail!("{} problem(s) found in {}. Fix them before running it.", diagnostics.len(), args.spec);
+      }
+      load_feature(&args.spec)?
+    };
+    debug!("Feature data: {:?}", feature);
+    let mut transcript = Transcript::new(feature.description.clone());
+
+    /* Remote JSON mode and API JSON mode: the docId is used by the remove
     feature line further down */
-    let doc_id = feature_data["docId"].as_str().unwrap().to_string();
-    
-    let steps = &mut feature_data["steps"];
-    let steps_immut = feature_data_immut["steps"].as_array().unwrap();
+    let doc_id = feature.doc_id.clone();
 
     // Create Autocode's own .env using feature data
-    if !Path::new(".env").exists() {
-      fs::File::create(".env")
-          .expect("Failed to create .env file");
+    if let Some(autocode_dotenv) = &feature.autocode_dotenv {
+      fs::write(".env", autocode_dotenv).context("Failed to write .env file")?;
     }
-    fs::write(".env", feature_data_immut["autocodeDotenv"].as_str().unwrap()).expect("Failed to write .env file");
     dotenv().ok();
 
-    if std::env::var("CLONING").unwrap() == "true" {
-      println!("Cloning reads true.");
-      clone_autocode(feature_data_immut["autocodeDotenv"].as_str().unwrap(), feature_data_immut["serviceJSON"].as_str().unwrap()).await;
+    let cloning = std::env::var("CLONING").unwrap_or_default() == "true" && cli.repo_dir.is_none();
+    // Kept until the run ends so the server is killed with it
+    let mut _api_server = None;
+    if cloning {
+      info!("Cloning reads true.");
+      let autocode_dotenv = feature.autocode_dotenv.clone().unwrap_or_default();
+      let service_json = feature.service_json.clone().unwrap_or_default();
+      match clone_autocode(&autocode_dotenv, &service_json, &options).await {
+        Ok(api_server) => _api_server = Some(api_server),
+        Err(err) => error!("Failed to clone express-autocode-api: {:#}", err),
+      }
     }
 
-    // Clone repository if needed
-    let first_step = &steps_immut[0];
-    let cloned_dir: Option<PathBuf> = if let Ok(cloning) = std::env::var("CLONING") {
-      if cloning == "true" {
-          if let Some(repo_url) = feature_data_immut["repoURL"].as_str() {
-              Some(clone_repository(repo_url, feature_data_immut["dotenvContents"].as_str().unwrap(), first_step["testPath"].as_str().unwrap()).await.unwrap())
-          } else {
-              eprintln!("CLONING=true but repoURL is not provided in the feature data.");
-              None
-          }
-      } else {
-          None
+    // Use the given checkout, or clone the repository if needed
+    let first_test_path = feature.steps.first().and_then(|step| step.test_path.clone()).unwrap_or_default();
+    let cloned_dir = match &cli.repo_dir {
+      Some(repo_dir) => repo_dir.clone(),
+      None if cloning => {
+        let repo_url = feature.repo_url.as_deref()
+          .context("CLONING=true but repoURL is not provided in the feature data.")?;
+        clone_repository(repo_url, feature.dotenv_contents.as_deref().unwrap_or_default()).await?
       }
-  } else {
+      None => anyhow::bail!("No repository to work in. Pass --repo-dir or set CLONING=true."),
+    };
+    // Prompts name files by their full path, which differs between checkouts
+    if let Some(cassette) = &options.cassette {
+      cassette.substitute(&cloned_dir.to_string_lossy(), "<repo>");
+    }
+
+    // Each run works on its own branch, committing every step that passes
+    let base_commit = if is_git_repo(&cloned_dir) {
+      if has_uncommitted_changes(&cloned_dir)? {
+        anyhow::bail!("{} has uncommitted changes. Commit or stash them before running a feature.", cloned_dir.display());
+      }
+      let branch = feature_branch_name(feature.description.as_deref().unwrap_or_default());
+      create_branch(&cloned_dir, &branch)?;
+      info!("Working on branch {}.", branch);
+      Some(head_commit(&cloned_dir)?)
+    } else {
+      warn!("{} is not a git repository, so steps won't be committed or rolled back.", cloned_dir.display());
       None
-  };
+    };
 
-  // Check if cloned_dir is uninitialized
-  if cloned_dir.is_none() {
-      // Handle uninitialized cloned_dir
-      eprintln!("cloned_dir is uninitialized.");
-      return;
-  }
-  let cloned_dir = cloned_dir.unwrap(); // Unwrap cloned_dir safely since we've checked it
-  let mut successful = true;
-  for step in steps.as_array_mut().unwrap() {
-      if let Err(err) = execute_step(step, cloned_dir.clone()).await {
+    let mut dev_server = None;
+    if cloning || cli.serve {
+      let port = url::Url::parse(&first_test_path).ok().and_then(|url| url.port_or_known_default());
+      let server = DevServer::start(DevServerConfig::npm_run_dev(cloned_dir.clone(), port))?;
+      let mut watch = server.watch();
+      info!("Dev server started on port {}.", server.port());
+      for step in feature.steps.iter_mut() {
+        step.test_path = step.test_path.as_deref().map(|test_path| server.local_url(test_path));
+      }
+      if let Some(test_path) = feature.steps.first().and_then(|step| step.test_path.as_deref()) {
+        match wait_until_served(test_path, Some(&mut watch), options.ready_timeout).await {
+          Readiness::Ready => info!("{} is being served.", test_path),
+          Readiness::CompileError(report) => warn!("The repo does not compile before any changes:\n{}", report),
+          Readiness::TimedOut => warn!("{} was not served within {:?}.", test_path, options.ready_timeout),
+        }
+      }
+      dev_server = Some(server);
+    }
+
+    // Steps run the feature's test suite unless they name their own
+    let mut steps = std::mem::take(&mut feature.steps);
+    for step in steps.iter_mut() {
+      inherit_settings(step, &feature, &options);
+    }
+    feature.steps = steps;
+
+    let mut successful = true;
+    let mut last_good_commit = base_commit.clone();
+    let mut replans_left = options.replan_budget;
+    let mut i = 0;
+    while i < feature.steps.len() {
+      let step = &mut feature.steps[i];
+      let llm = options.llm.merged(feature.llm.as_ref()).merged(step.llm.as_ref());
+      let provider = provider_for(&llm, &options)?;
+      let budget = PromptBudget::for_config(&llm)?;
+      let step_start = Instant::now();
+      let mut step_transcript = StepTranscript::new(step);
+      let result = execute_step(step, cloned_dir.clone(), &options, provider.as_ref(), &budget, dev_server.as_ref(), &mut step_transcript).await;
+      step_transcript.passed = result.is_ok();
+      step_transcript.error = result.as_ref().err().map(|err| format!("{:#}", err));
+      step_transcript.duration_ms = elapsed_ms(step_start);
+      transcript.steps.push(step_transcript);
+      transcript.duration_ms = elapsed_ms(run_start);
+      let report_path = save_transcript(&transcript, &run_dir);
+      let target_path = step.target_file().map(|file| file.file_path.clone()).unwrap_or_default();
+      if let Err(err) = result {
+          eprintln!("Error executing step {}: {:#}", i + 1, err);
+          if let Some(report_path) = report_path {
+            eprintln!("See {} for the prompts, responses and logs of each attempt.\n", report_path.display());
+          }
+          if let Some(commit) = &last_good_commit {
+            reset_to(&cloned_dir, commit, &[&target_path])?;
+            info!("Reset {} to the last passing step.", cloned_dir.display());
+          }
+          // The planner gets a limited number of chances per run to work around a failed step
+          if replans_left > 0 {
+            replans_left -= 1;
+            let failed = transcript.steps.last().cloned().unwrap_or_default();
+            match replan(&feature, i, &failed, &cloned_dir, &llm, &options, dev_server.as_ref()).await {
+              Ok(sub_steps) => {
+                info!("Replanned step {} into {} step(s), {} replan(s) left.", i + 1, sub_steps.len(), replans_left);
+                if let Some(failed) = transcript.steps.last_mut() {
+                  failed.replanned_into = sub_steps.iter().map(|sub_step| sub_step.description.clone()).collect();
+                }
+                save_transcript(&transcript, &run_dir);
+                feature.steps.splice(i..=i, sub_steps);
+                continue;
+              }
+              Err(err) => eprintln!("Could not replan step {}: {:#}", i + 1, err),
+            }
+          }
           successful = false;
-          let error_message = "Custom error message system not yet implemented.";
-          eprintln!("Error executing step: {}\n", error_message);
           break;
       }
-  }
+      if last_good_commit.is_some() {
+        if let Some(commit) = commit_files(&cloned_dir, &[&target_path], &step_commit_message(i, step))? {
+          info!("Committed step {} as {}.", i + 1, commit);
+          last_good_commit = Some(commit);
+        }
+      }
+      i += 1;
+    }
 
-  if successful {
+    transcript.passed = successful;
+    transcript.duration_ms = elapsed_ms(run_start);
+    if let Some(report_path) = save_transcript(&transcript, &run_dir) {
+      info!("Run report saved to {}.", report_path.display());
+    }
+
+    if let Some(base_commit) = &base_commit {
+      println!("{}\n", summarize_range(&cloned_dir, base_commit)?);
+    }
+
+    if successful {
       println!("Feature completed. Tests passed at each step.\n");
-      // Remote JSON mode and API JSON mode: uncomment the line below
-      remove_feature(&doc_id).await.unwrap();
+      // Remote JSON mode and API JSON mode: features with a docId are removed
+      if let Some(doc_id) = doc_id {
+          recorded(options.cassette.as_deref(), "remove-feature", json!({ "docId": doc_id }), || remove_feature(&options.service, &doc_id)).await?;
+      }
+    }
+    Ok(successful)
+}
+
+// Fills in the steps of a spec from its description and the repo, and writes
+// the result for the user to review before running it
+async fn plan(args: &PlanArgs, cli: &Cli) -> Result<()> {
+    let options = cli.run_options()?;
+    // Resolved now, since cloning changes the current directory
+    let output = env::current_dir()?.join(args.output.clone().unwrap_or_else(|| PathBuf::from(&args.spec)));
+    let spec_text = fs::read_to_string(&args.spec)
+      .with_context(|| format!("Failed to read file: {}", args.spec))?;
+    let spec: serde_json::Value = serde_json::from_str(&spec_text)
+      .with_context(|| format!("Failed to deserialize JSON data from file: {}", args.spec))?;
+    let existing_steps = spec.get("steps").and_then(|steps| steps.as_array()).map_or(0, Vec::len);
+    if existing_steps > 0 && !args.force {
+      anyhow::bail!("{} already has {} step(s). Pass --force to replace them.", args.spec, existing_steps);
+    }
+    let llm_settings: Option<LlmConfig> = spec.get("llm").cloned().map(serde_json::from_value).transpose()
+      .context("Failed to read the llm settings")?;
+    let llm = options.llm.merged(llm_settings.as_ref());
+
+    dotenv().ok();
+    let repo_dir = match &cli.repo_dir {
+      Some(repo_dir) => repo_dir.clone(),
+      None if std::env::var("CLONING").unwrap_or_default() == "true" => {
+        let repo_url = spec.get("repoURL").and_then(|url| url.as_str())
+          .context("CLONING=true but repoURL is not provided in the feature data.")?;
+        let dotenv_contents = spec.get("dotenvContents").and_then(|contents| contents.as_str()).unwrap_or_default();
+        clone_repository(repo_url, dotenv_contents).await?
+      }
+      None => anyhow::bail!("No repository to plan for. Pass --repo-dir or set CLONING=true."),
+    };
+
+    let provider = provider_for(&llm, &options)?;
+    let budget = PromptBudget::for_config(&llm)?;
+    // Half the context for the repo, leaving room for the plan and any retries
+    let overview = repo_overview(&repo_dir, budget.available() / 2, &budget.counter)?;
+    let planned = plan_feature(provider.as_ref(), &spec, &repo_dir, &overview, &args.base_url).await?;
+    fs::write(&output, serde_json::to_string_pretty(&planned)? + "\n")
+      .with_context(|| format!("Failed to write {}", output.display()))?;
+
+    let steps = planned["steps"].as_array().cloned().unwrap_or_default();
+    println!("Planned {} step(s) in {}:", steps.len(), output.display());
+    for (i, step) in steps.iter().enumerate() {
+      println!("{}. {} ({})", i + 1, step["description"].as_str().unwrap_or_default(), step["target"].as_str().unwrap_or_default());
+    }
+    println!("Review and edit them, then run it with `run {}`.", output.display());
+    Ok(())
+}
+
+// The model a step talks to, through the cassette when one is in use
+fn provider_for(llm: &LlmConfig, options: &RunOptions) -> Result<Box<dyn LlmProvider>> {
+  match &options.cassette {
+    Some(cassette) => cassette_provider(llm, cassette.clone()),
+    None => build_provider(llm),
+  }
+}
+
+// Saves the run so far, returning the report path. A run isn't failed over its transcript.
+fn save_transcript(transcript: &Transcript, run_dir: &std::path::Path) -> Option<PathBuf> {
+  match transcript.save(run_dir) {
+    Ok(report_path) => Some(report_path),
+    Err(err) => {
+      warn!("Failed to save the run transcript: {:#}", err);
+      None
+    }
+  }
+}
+
+// Layers the feature's and the command line's settings under the step's own
+fn inherit_settings(step: &mut Step, feature: &Feature, options: &RunOptions) {
+  step.add_target_file();
+  if step.test_command.is_none() {
+    step.test_command = feature.test_command.clone();
+    step.junit_report = step.junit_report.clone().or_else(|| feature.junit_report.clone());
+  }
+  step.typecheck_command = step.typecheck_command.clone().or_else(|| feature.typecheck_command.clone()).or_else(|| options.typecheck_command.clone());
+  step.lint_command = step.lint_command.clone().or_else(|| feature.lint_command.clone()).or_else(|| options.lint_command.clone());
+}
+
+// Asks the planner for steps to take the place of the failed one at `index`.
+// They keep its settings, but not its expectations, which were written for
+// the whole change.
+async fn replan(feature: &Feature, index: usize, failed: &StepTranscript, cloned_dir: &std::path::Path, llm: &LlmConfig, options: &RunOptions, dev_server: Option<&DevServer>) -> Result<Vec<Step>> {
+  let step = &feature.steps[index];
+  let provider = provider_for(llm, options)?;
+  let budget = PromptBudget::for_config(llm)?;
+  let overview = repo_overview(cloned_dir, budget.available() / 2, &budget.counter)?;
+  let base_url = step.test_path.as_deref()
+    .and_then(|test_path| url::Url::parse(test_path).ok())
+    .map(|url| url.origin().ascii_serialization())
+    .unwrap_or_else(|| "http://localhost:3000".to_string());
+  let spec = serde_json::to_value(feature)?;
+  let planned = replan_step(provider.as_ref(), &spec, failed, cloned_dir, &overview, &base_url).await?;
+
+  let mut sub_steps = Vec::new();
+  for value in planned {
+    let planned_step: Step = serde_json::from_value(value).context("Failed to read a replanned step")?;
+    let mut sub_step = Step {
+      description: planned_step.description,
+      target: planned_step.target,
+      files: planned_step.files,
+      test_path: planned_step.test_path.map(|test_path| match dev_server {
+        Some(server) => server.local_url(&test_path),
+        None => test_path,
+      }),
+      expect: None,
+      ..step.clone()
+    };
+    sub_step.add_target_file();
+    sub_steps.push(sub_step);
+  }
+  Ok(sub_steps)
+}
+
+// Subject line from the step description, kept within the usual 72 columns
+fn step_commit_message(index: usize, step: &Step) -> String {
+  let mut subject = format!("Step {}: {}", index + 1, step.description.lines().next().unwrap_or_default().trim());
+  if subject.chars().count() > 72 {
+    subject = subject.chars().take(69).collect::<String>() + "...";
+  }
+  format!("{}\n\n{}\n\nTarget: {}", subject, step.description.trim(), step.target)
+}
+
+// Prints every problem found in the spec and returns the process exit code
+fn validate(spec_path: &str) -> i32 {
+  match validate_feature_file(spec_path) {
+    Ok(diagnostics) if diagnostics.is_empty() => {
+      println!("{} is a valid feature spec.", spec_path);
+      0
+    }
+    Ok(diagnostics) => {
+      for diagnostic in &diagnostics {
+        eprintln!("{}: {}", spec_path, diagnostic);
+      }
+      eprintln!("{} problem(s) found in {}.", diagnostics.len(), spec_path);
+      1
+    }
+    Err(err) => {
+      eprintln!("{:#}", err);
+      2
+    }
   }
 }
 
-async fn clone_autocode(dotenv_contents: &str, service_json: &str) -> Result<PathBuf> {
+async fn clone_autocode(dotenv_contents: &str, service_json: &str, options: &RunOptions) -> Result<DevServer> {
   let repo_url = "https://github.com/emoryhubbard/express-autocode-api.git";
   // Get the current directory
   let original_dir = std::env::current_dir()
@@ -147,27 +466,21 @@ async fn clone_autocode(dotenv_contents: &str, service_json: &str) -> Result<Pat
       return Err(anyhow::anyhow!("Failed to install npm dependencies"));
   }
 
-  /*let _output = tokio::process::Command::new("npm")
-        .arg("run")
-        .arg("dev")
-        .output();*/
-  // Run npm run dev in a terminal
-  Command::new("gnome-terminal")
-      .arg("--wait") // Add the --wait option to keep the terminal open
-      .arg("--")
-      .arg("npm")
-      .arg("run")
-      .arg("dev")
-      .spawn()
-      .with_context(|| "Failed to execute npm run dev in a terminal")?;
-  thread::sleep(Duration::from_secs(6)); // giving TypeScript time to compile code
+  // Run npm run dev, on the port the library functions call
+  let api_server = DevServer::start(DevServerConfig::npm_run_dev(clone_dir.clone(), options.service.port()))?;
+  if options.service.port() != Some(api_server.port()) {
+    warn!("express-autocode-api started on port {}, but requests go to {}. Pass --api-url to match.", api_server.port(), options.service.url(""));
+  }
+  if !wait_for_port(api_server.port(), options.ready_timeout).await {
+    warn!("express-autocode-api is not listening on port {} yet.", api_server.port());
+  }
 
   env::set_current_dir(original_dir.clone())
       .with_context(|| format!("Failed to change directory to {}", original_dir.display()))?;
-  Ok((clone_dir))
+  Ok(api_server)
 }
 
-async fn clone_repository(repo_url: &str, dotenv_contents: &str, test_path: &str) -> Result<PathBuf> {
+async fn clone_repository(repo_url: &str, dotenv_contents: &str) -> Result<PathBuf> {
     // Get the current directory
     let current_dir = std::env::current_dir()
         .with_context(|| "Failed to get current directory")?;
@@ -211,169 +524,277 @@ async fn clone_repository(repo_url: &str, dotenv_contents: &str, test_path: &str
     if !output.status.success() {
         return Err(anyhow::anyhow!("Failed to install npm dependencies"));
     }
-    // Run npm run dev in a terminal
-    Command::new("gnome-terminal")
-        .arg("--wait") // Add the --wait option to keep the terminal open
-        .arg("--")
-        .arg("npm")
-        .arg("run")
-        .arg("dev")
-        .spawn()
-        .with_context(|| "Failed to execute npm run dev in a terminal")?;
-
-    thread::sleep(Duration::from_secs(6)); // giving NextJS time to compile code
-    let _ = log_and_run(test_path, "false").await;
-    Ok((clone_dir))
-}
-
-async fn execute_step_test_edit(step: &mut Value) -> Result<()> {
-  if let Some(files) = step["files"].as_array_mut() {
-      for file in files {
-          add_file_contents(file);
-      }
-  }
-  let new_function_contents= r#"function Home() {
-      // this should appear
-      return (
-          <>
-          <title>Tailwindify Home Page</title>
-          <Header />
-          <Footer />
-          <Script type='module' src="/js/home.js" />
-          </>
-      )
-  }"#.to_string();
-  println!("Extracted code: {}", new_function_contents);
-  let _ = create_or_modify(&step, &new_function_contents).await;
-
-  Ok(())
+    Ok(clone_dir)
 }
-async fn execute_step_no_debug(step: &mut Value) -> Result<()> {
-  if let Some(files) = step["files"].as_array_mut() {
-      for file in files {
-          add_file_contents(file);
-      }
-  }
 
-  let full_prompt = get_prompt(&step);
-  let api_key = std::env::var("API_KEY").context("API_KEY environment variable not found")?;
-  let response = prompt(&full_prompt, &api_key).await?;
-  println!("Response: {}", response);
-  
-  let js_content = extract_jsx(&response).await?;
-  println!("Extracted code: {}", js_content);
-  let _ = create_or_modify(&step, &js_content).await;
-
-  Ok(())
-}
-async fn execute_step(step: &mut Value, cloned_dir: PathBuf) -> Result<()> {
-  if let Some(files) = step["files"].as_array_mut() {
-      for file in files {
-        if std::env::var("CLONING").unwrap() == "true" {
-          add_full_path(file, cloned_dir.clone());
-        }
-        add_file_contents(file);
+// Instructions that hold for every turn of a step's conversation
+const SYSTEM_PROMPT: &str = "You are an expert React and TypeScript developer adding a feature to an app one small, tested step at a time. When asked for code, reply with the whole file in a single code block, in the same language as the file.";
+
+// Runs one step, recording each attempt in `transcript` as it goes
+async fn execute_step(step: &mut Step, cloned_dir: PathBuf, options: &RunOptions, provider: &dyn LlmProvider, budget: &PromptBudget, dev_server: Option<&DevServer>, transcript: &mut StepTranscript) -> Result<()> {
+  if options.context_tokens > 0 {
+    if let Some(target) = step.target_file() {
+      let listed: Vec<String> = step.files.iter().map(|file| file.file_path.clone()).collect();
+      let discovered = discover_context(&cloned_dir, &target.file_path, &listed, options.context_tokens, &budget.counter)?;
+      if !discovered.is_empty() {
+        let names: Vec<&str> = discovered.iter().map(|file| file.file_name.as_str()).collect();
+        info!("Added {} to the context from the imports of {}.", names.join(", "), step.target);
       }
+      step.files.extend(discovered);
+    }
+  }
+  for file in step.files.iter_mut() {
+    add_full_path(file, cloned_dir.clone());
+    add_file_contents(file);
   }
 
+  let max_attempts = step.max_attempts.unwrap_or(options.max_attempts);
+  let strategy = step.repair_strategy.unwrap_or(options.repair_strategy);
   let mut passing = false;
-  let mut code_attempts = Vec::new();
-  let mut logs = Vec::new();
-  let mut passing_responses = Vec::new();
-  let mut curr_prompt = get_prompt(&step);
-  let mut user_prompt = get_prompt(&step);
-  let mut trimmed_code = String::new();
-  let api_key = std::env::var("CHATGPT_APIKEY").context("API_KEY environment variable not found")?;
-
-  println!("\ncurr_prompt: {}", &curr_prompt);
-  let mut code_attempt = prompt(&curr_prompt, &api_key).await?;
-  println!("\ncode_attempt: {}", code_attempt);
-
-  let max_attempts = 3;
-  for i in 0..max_attempts {
-      code_attempts.push(code_attempt.clone());
-      trimmed_code = extract_jsx(&code_attempt).await?;
-      println!("\ntrimmed_code: {}", trimmed_code);
-      create_or_modify(&step, &trimmed_code).await?;
-      thread::sleep(Duration::from_secs(3)); // giving NextJS time to compile changed code
-      if let Some(test_path) = step["testPath"].as_str() {
-        let curr_logs = log_and_run(test_path, &step["showHTML"].as_str().unwrap().to_lowercase()).await.unwrap();
-        println!("\ncurr_logs: {}", curr_logs);
-        logs.push(curr_logs);
-      }
-      code_attempt = get_passing_response(&trimmed_code, &logs[i], &curr_prompt, &api_key, step["target"].as_str().unwrap()).await?;
-      //println!("\npassing_response: {}", passing_response);
-      passing_responses.push(code_attempt.clone());
-      passing = is_passing(&passing_responses[i]);
-      println!("\ncode_attempt: {}", code_attempt);
-      if !passing {
-        //println!("\nlogs going to get_next_prompt: {}", &logs[i]);
-        //curr_prompt = get_next_prompt(&trimmed_code, &logs[i], &user_prompt, &passing_responses[i], &step);
+  let curr_prompt = get_prompt(step, budget)?;
+  transcript.prompt = curr_prompt.clone();
+
+  debug!("\ncurr_prompt: {}", &curr_prompt);
+  let mut messages = vec![Message::system(SYSTEM_PROMPT), Message::user(&curr_prompt)];
+  let mut code_attempt = provider.chat(&messages).await?;
+  messages.push(Message::assistant(&code_attempt));
+  debug!("\ncode_attempt: {}", code_attempt);
+
+  for attempt_number in 1..=max_attempts {
+      let attempt_start = Instant::now();
+      let mut attempt = Attempt { response: code_attempt.clone(), ..Attempt::default() };
+      let trimmed_code = extract_code(&code_attempt, &step.target, options).await?;
+      debug!("\ntrimmed_code: {}", trimmed_code);
+      attempt.extracted_code = trimmed_code.clone();
+      let target_path = step.target_file().map(|file| file.file_path.clone()).unwrap_or_default();
+      let (evaluation, verdict) = if let Some(problem) = syntax_problem(&trimmed_code, source_type_for(&step.target)) {
+        // Not worth writing, let alone loading in a browser
+        attempt.static_errors = problem;
+        static_verdict("The code did not parse, so it was not written.", &attempt.static_errors)
       } else {
-          break;
+        let before = fs::read_to_string(&target_path).unwrap_or_default();
+        let watch = dev_server.map(DevServer::watch);
+        if let Some(problem) = create_or_modify(step, &trimmed_code, options).await? {
+          attempt.static_errors = problem;
+          static_verdict("The code did not parse once spliced into the file, so it was not written.", &attempt.static_errors)
+        } else {
+          attempt.diff = file_diff(&step.target, &before, &fs::read_to_string(&target_path).unwrap_or_default());
+          let target_in_repo = std::path::Path::new(&target_path).strip_prefix(&cloned_dir).map(|path| path.to_string_lossy().to_string()).unwrap_or_else(|_| target_path.clone());
+          match run_static_checks(&static_checks(step), &cloned_dir, &target_in_repo, options.test_timeout).await {
+            Some(problem) => {
+              attempt.static_errors = problem;
+              static_verdict("The code failed a static check, so the page was not loaded.", &attempt.static_errors)
+            }
+            None => verify_attempt(step, &cloned_dir, &curr_prompt, watch, options, provider, &mut attempt).await?,
+          }
+        }
+      };
+      debug!("\nevaluation: {}", evaluation);
+      passing = verdict.passed;
+      attempt.evaluation = evaluation;
+      attempt.verdict = Some(verdict.clone());
+      attempt.passed = passing;
+      attempt.duration_ms = elapsed_ms(attempt_start);
+      transcript.attempts.push(attempt.clone());
+      if passing || attempt_number == max_attempts {
+        break;
       }
+
+      info!("Attempt {} of {} failed: {}", attempt_number, max_attempts, verdict.reason);
+      let test_results = get_test_results(&attempt, &verdict.reason, step);
+      code_attempt = match (strategy, verdict.corrected_code) {
+        (RepairStrategy::Evaluator, Some(corrected_code)) => {
+          messages.push(Message::tool_result(&test_results));
+          corrected_code
+        }
+        (RepairStrategy::Fresh, _) => {
+          let next_prompt = get_next_prompt(&attempt, &step.description, &verdict.reason, step);
+          messages = vec![Message::system(SYSTEM_PROMPT), Message::user(&next_prompt)];
+          provider.chat(&messages).await?
+        }
+        _ => {
+          messages.push(Message::tool_result(&test_results));
+          messages.push(Message::user(&format!("Could you write a new, corrected {:?}? Please include the whole file in your response.", step.target)));
+          provider.chat(&messages).await?
+        }
+      };
+      messages.push(Message::assistant(&code_attempt));
+      debug!("\ncode_attempt: {}", code_attempt);
   }
-  //println!("{}", get_debug_details(&trimmed_code, &code_attempts, &logs, &passing_responses)?);
+  transcript.conversation = messages;
   if !passing {
-    //println!("{}", get_debug_details(&trimmed_code, &code_attempts, &logs, &passing_responses)?);
-    anyhow::bail!("Debugging attempts failed. Aborting execution.");
+    anyhow::bail!("Debugging attempts failed after {} attempt(s).", max_attempts);
   }
   Ok(())
 }
 
-fn add_full_path(file: &mut Value, cloned_dir: PathBuf) {
-  let file_path = file["filePath"].as_str().unwrap();
-  let updated_file_path = cloned_dir.join(file_path);
-  // Update the "filePath" field in the file object
-  file["filePath"] = json!(updated_file_path.to_string_lossy());
-  println!("Updated filePath: {}", file["filePath"]);
+// Loads the page and runs the test suite for code that has been written,
+// and decides whether the attempt passed
+async fn verify_attempt(step: &Step, cloned_dir: &std::path::Path, curr_prompt: &str, mut watch: Option<CompileWatch>, options: &RunOptions, provider: &dyn LlmProvider, attempt: &mut Attempt) -> Result<(String, Verdict)> {
+  let mut curr_build_errors = Vec::new();
+  let mut run_report = RunReport::default();
+  if let Some(test_path) = step.test_path.as_deref() {
+    let needs_html = step.expect.as_ref().is_some_and(Expectations::needs_html);
+    // A replayed page load doesn't need the page to be served
+    let (kind, request) = page_load_key(test_path, step.show_html || needs_html, options);
+    let replaying = options.cassette.as_ref().is_some_and(|cassette| cassette.will_replay(kind, &request));
+    if !replaying {
+      match wait_until_served(test_path, watch.as_mut(), options.ready_timeout).await {
+        Readiness::CompileError(report) => curr_build_errors.push(report),
+        Readiness::TimedOut => warn!("{} was not served within {:?}, running it anyway.", test_path, options.ready_timeout),
+        Readiness::Ready => {}
+      }
+    }
+    run_report = run_test_path(test_path, step.show_html || needs_html, options).await;
+    // HTML captured only for the selectors isn't shown to the model
+    let curr_logs = if step.show_html {
+      run_report.to_logs()
+    } else {
+      RunReport { html: None, ..run_report.clone() }.to_logs()
+    };
+    debug!("\ncurr_logs: {}", curr_logs);
+    attempt.console_logs = curr_logs;
+  }
+  // Client bundles are built while the browser loads the page, so look again
+  if let Some(watch) = watch.as_mut() {
+    if let Some(CompileEvent::Failed(report)) = watch.check().await {
+      curr_build_errors.push(report);
+    }
+  }
+  if !curr_build_errors.is_empty() {
+    debug!("\ncurr_build_errors: {}", curr_build_errors.join("\n"));
+  }
+  attempt.build_errors = curr_build_errors.join("\n");
+  let mut suite = None;
+  if let Some(test_command) = step.test_command.as_deref() {
+    let junit_report = step.junit_report.as_deref().map(std::path::Path::new);
+    let result = run_test_command(test_command, cloned_dir, junit_report, options.test_timeout).await
+      .unwrap_or_else(|err| SuiteResult {
+        command: test_command.to_string(),
+        output_tail: format!("{:#}", err),
+        ..SuiteResult::default()
+      });
+    attempt.test_suite = result.summary();
+    debug!("\ntest_suite: {}", attempt.test_suite);
+    suite = Some(result);
+  }
+  Ok(match suite_verdict(step, suite.as_ref()).or_else(|| expectation_verdict(step, &run_report, &attempt.build_errors)) {
+    Some(verdict) => (verdict.reason.clone(), verdict),
+    None => get_passing_response(&attempt.extracted_code, &attempt.console_logs, &attempt.build_errors, curr_prompt, provider, &step.target).await?,
+  })
 }
 
-fn add_file_contents(file: &mut Value) {
-  let file_path = file["filePath"].as_str().unwrap();
-  if let Ok(contents) = fs::read_to_string(file_path) {
-      file.as_object_mut().unwrap().insert("fileContents".to_string(), json!(contents));
-  } else {
-      println!("Error reading file: {}", file_path);
+// The typecheck and lint gates of a step, in the order they run
+fn static_checks(step: &Step) -> Vec<StaticCheck> {
+  let mut checks = Vec::new();
+  if let Some(command) = &step.typecheck_command {
+    checks.push(StaticCheck { name: "Typecheck", command: command.clone() });
+  }
+  if let Some(command) = &step.lint_command {
+    checks.push(StaticCheck { name: "Lint", command: command.clone() });
   }
+  checks
 }
 
-fn get_prompt(step: &Value) -> String {
-  let description = step["description"].as_str().unwrap();
-  let mut prompt = format!("Could you write a new {} with this modification: \"{}\". In addition, could you write a simple console log statement(s) within its code to verify the change is working, which is highly likely to run (not lost in a function that isn't called)?", step["target"], description);
+// A failed attempt decided before the page was loaded
+fn static_verdict(reason: &str, problem: &str) -> (String, Verdict) {
+  // The full errors go in their own section of the repair prompt
+  let first_line = problem.lines().next().unwrap_or_default();
+  let verdict = Verdict { passed: false, reason: format!("{} {}", reason, first_line), corrected_code: None };
+  (verdict.reason.clone(), verdict)
+}
 
-  if let Some(files) = step["files"].as_array() {
-      for file in files {
-          let file_name = file["fileName"].as_str().unwrap();
-          let file_path = file["filePath"].as_str().unwrap();
-          let file_contents = file["fileContents"].as_str().unwrap_or("No file contents");
-          prompt.push_str(&format!(" Here is the current {} located at {}: \"{}\"\n", file_name, file_path, file_contents));
-      }
+async fn extract_code(response: &str, target: &str, options: &RunOptions) -> Result<String> {
+  match options.extractor {
+    Backend::Native => Ok(extract_jsx(response, source_type_for(target))),
+    Backend::Service => recorded(options.cassette.as_deref(), "extract-jsx", json!({ "inputString": response }), || extract_jsx_service(&options.service, response)).await,
   }
-  
-  prompt
 }
-async fn create_or_modify(step: &Value, new_contents: &String) -> Result<()> {
-    let target_file_name = step["target"].as_str().context("Target file name not found in step")?;
 
-    let files = step["files"].as_array().context("Files array not found in step")?;
+// Loads the test page and returns what happened. Failures to run it become a
+// page error, so the model and the expectations get to see them
+async fn run_test_path(test_path: &str, show_html: bool, options: &RunOptions) -> RunReport {
+  let (kind, request) = page_load_key(test_path, show_html, options);
+  let cassette = options.cassette.as_deref();
+  let show_html_param = show_html.to_string();
+  let result = match options.runner {
+    Backend::Native => recorded(cassette, kind, request, || log_and_run(test_path, show_html, &options.browser)).await,
+    Backend::Service => recorded(cassette, kind, request, || log_and_run_service(&options.service, test_path, &show_html_param)).await
+      .map(|logs: String| RunReport::from_logs(&logs)),
+  };
+  result.unwrap_or_else(|err| {
+    warn!("Failed to run {}: {:#}", test_path, err);
+    RunReport {
+      page_errors: vec![PageError { message: format!("{:#}", err), stack: None }],
+      ..RunReport::default()
+    }
+  })
+}
+
+// What a page load is recorded as. The runners return different things, so
+// they are recorded apart.
+fn page_load_key(test_path: &str, show_html: bool, options: &RunOptions) -> (&'static str, serde_json::Value) {
+  let kind = match options.runner {
+    Backend::Native => "log-and-run",
+    Backend::Service => "log-and-run-service",
+  };
+  (kind, json!({ "testPath": test_path, "showHTML": show_html }))
+}
+
+fn add_full_path(file: &mut FileRef, cloned_dir: PathBuf) {
+  let updated_file_path = cloned_dir.join(&file.file_path);
+  // Update the "filePath" field in the file object
+  file.file_path = updated_file_path.to_string_lossy().to_string();
+  debug!("Updated filePath: {}", file.file_path);
+}
 
-    let target_file_path = files.iter()
-        .find(|file| file["isTarget"].as_bool() == Some(true))
-        .and_then(|file| file["filePath"].as_str())
+fn add_file_contents(file: &mut FileRef) {
+  if let Ok(contents) = fs::read_to_string(&file.file_path) {
+      file.file_contents = Some(contents);
+  } else {
+      warn!("Error reading file: {}", file.file_path);
+  }
+}
+
+// The step's first prompt, fitted to the model's context window
+fn get_prompt(step: &Step, budget: &PromptBudget) -> Result<String> {
+  let mut prompt = format!("Could you write a new {:?} with this modification: \"{}\". In addition, could you write a simple console log statement(s) within its code to verify the change is working, which is highly likely to run (not lost in a function that isn't called)?", step.target, step.description);
+  if source_type_for(&step.target).is_typescript() {
+      prompt.push_str(" Keep it in TypeScript, with types for any new props, state and functions, and keep its existing interfaces, type aliases and enums unless the change needs them altered.");
+  }
+
+  let target = step.target_file().context("Target file not found in files")?;
+  let helpers: Vec<FileRef> = step.files.iter().filter(|file| !file.is_target).cloned().collect();
+  assemble_prompt(&prompt, target, &helpers, budget)
+}
+// Writes the new contents to the target, spliced into it with the run's
+// splicer when they're much shorter. Returns why nothing was written if the
+// spliced file doesn't parse.
+async fn create_or_modify(step: &Step, new_contents: &str, options: &RunOptions) -> Result<Option<String>> {
+    let target_file_name = &step.target;
+
+    let target_file_path = step.target_file()
+        .map(|file| file.file_path.as_str())
         .context("Target file path not found in files")?;
 
-    let existing_contents = match fs::read_to_string(target_file_path) {
-        Ok(contents) => contents,
-        Err(_) => String::new(), // File doesn't exist yet
-    };
+    let existing_contents = fs::read_to_string(target_file_path).unwrap_or_default(); // File may not exist yet
 
     // Check if the new_contents is less than 50% of the existing_contents
     let new_lines = new_contents.lines().count();
     let existing_lines = existing_contents.lines().count();
     if new_lines < existing_lines / 2 {
-        // Replace the existing function with the new one
-        fs::write(target_file_path, get_updated_functions(&existing_contents, new_contents).await.unwrap())
+        // Replace the existing functions with the new ones
+        let updated_contents = match options.splicer {
+            Backend::Service => {
+                let request = json!({ "existingContents": existing_contents, "newContents": new_contents });
+                recorded(options.cassette.as_deref(), "get-updated-functions", request, || get_updated_functions_service(&options.service, &existing_contents, new_contents)).await?
+            }
+            Backend::Native => get_updated_functions(&existing_contents, new_contents, source_type_for(target_file_name))?,
+        };
+        if let Some(problem) = syntax_problem(&updated_contents, source_type_for(target_file_name)) {
+            return Ok(Some(format!("After splicing the code into the existing {}:\n{}", target_file_name, problem)));
+        }
+        fs::write(target_file_path, updated_contents)
             .with_context(|| format!("Failed to write to file: {}", target_file_path))?;
     } else {
         // Write the new_contents to the file
@@ -381,86 +802,129 @@ async fn create_or_modify(step: &Value, new_contents: &String) -> Result<()> {
             .with_context(|| format!("Failed to write to file: {}", target_file_path))?;
     }
 
-    println!(
+    info!(
         "File {} {}.",
         target_file_name,
         if existing_contents.is_empty() { "created" } else { "modified" }
     );
 
-    Ok(())
+    Ok(None)
 }
-fn extract_functions(file_contents: &str) -> Vec<String> {
-  println!("Inside extract functions");
-  let re = regex::Regex::new(r"function\s+(\w+)\s*\(([^)]*)\)\s*\{(.*?)\}")
-      .unwrap();
-  let mut functions = Vec::new();
-
-  for capture in re.captures_iter(file_contents) {
-      let function_name = capture.get(1).unwrap().as_str().to_string();
-      let function_params = capture.get(2).unwrap().as_str().to_string();
-      let function_body = capture.get(3).unwrap().as_str().to_string();
-
-      let function_definition = format!(
-          "function {}({}) {{\n{}\n}}",
-          function_name, function_params, function_body
-      );
-      println!("Function definition: {}\n", function_definition);
-      functions.push(function_definition);
+// A failing test suite fails the attempt. A passing one decides it only when
+// the step has no page to check.
+fn suite_verdict(step: &Step, suite: Option<&SuiteResult>) -> Option<Verdict> {
+  let suite = suite?;
+  if !suite.passed {
+    return Some(Verdict { passed: false, reason: format!("The test suite failed. {}", suite.summary()), corrected_code: None });
   }
-
-  functions
+  if step.test_path.is_none() && step.expect.is_none() {
+    return Some(Verdict { passed: true, reason: suite.summary(), corrected_code: None });
+  }
+  None
 }
 
-fn get_function_name(function_definition: &str) -> Option<String> {
-  let re = regex::Regex::new(r"function\s+(\w+)\s*\(").unwrap();
-  if let Some(capture) = re.captures(function_definition) {
-      let function_name = capture.get(1).unwrap().as_str().to_string();
-      Some(function_name)
-  } else {
-      None
+// Decides the attempt from the step's expectations when they can: any unmet
+// expectation fails it, and meeting them all passes it unless the step also
+// wants the model's evaluation
+fn expectation_verdict(step: &Step, report: &RunReport, build_errors: &str) -> Option<Verdict> {
+  let expect = step.expect.as_ref()?;
+  let mut failures = check_expectations(expect, report);
+  if !build_errors.is_empty() {
+    failures.insert(0, "The dev server reported build errors".to_string());
+  }
+  if !failures.is_empty() {
+    return Some(Verdict {
+      passed: false,
+      reason: format!("These expectations were not met: {}", failures.join("; ")),
+      corrected_code: None,
+    });
+  }
+  if expect.evaluate {
+    return None;
   }
+  Some(Verdict { passed: true, reason: "Every expectation was met.".to_string(), corrected_code: None })
 }
 
-async fn get_passing_response(code: &str, logs: &str, user_prompt: &str, api_key: &String, target: &str) -> Result<String> {
+// Asks the evaluator for a verdict on the attempt, returning its reply with the
+// verdict. An unreadable reply is asked about once more, and if that is still
+// unclear the attempt counts as failed rather than guessing.
+async fn get_passing_response(code: &str, logs: &str, build_errors: &str, user_prompt: &str, provider: &dyn LlmProvider, target: &str) -> Result<(String, Verdict)> {
   let logs = if logs.is_empty() {
       "[no console log output was produced]".to_string()
   } else {
       logs.to_string()
   };
-  //println!("Logs from running the file: {}", logs);
 
-  let response_prompt = format!("Here is the code: {}\n\nNote that it should be doing exactly what the user wanted, which was '{}'. Based on the following logs, does this code look like it ran properly? (Note in React it is normal if logs repeat twice on component initialization) Console logs:\n{}\n[end of logs]\n\nIMPORTANT: Please include the word yes, or no, in your response for clarity, explain why, and provide a corrected \"{}\", if necessary (include any missing function calls, especially if the logs are empty yet functions are defined, in your corrected \"{}\").", code, user_prompt, logs, target, target);
-  let response = prompt(&response_prompt, &api_key).await?;
+  let response_prompt = format!("Here is the code: {}\n\nNote that it should be doing exactly what the user wanted, which was '{}'. Based on the following logs, does this code look like it ran properly? (Note in React it is normal if logs repeat twice on component initialization) Console logs:\n{}\n[end of logs]\n\n{}IMPORTANT: Reply with only a JSON object with these fields: \"passed\" (true or false), \"reason\" (explain why, based on the logs) and \"corrected_code\" (null if it passed, otherwise the whole corrected {:?} without Markdown fences; include any missing function calls, especially if the logs are empty yet functions are defined).", code, user_prompt, logs, build_errors_section(build_errors), target);
+  let schema = verdict_schema();
+  let mut messages = vec![Message::user(&response_prompt)];
+  let response = provider.chat_structured(&messages, "report_verdict", &schema).await?;
+  if let Evaluation::Verdict(verdict) = parse_verdict(&response) {
+    return Ok((response, verdict));
+  }
 
-  //println!("ChatGPT evaluation of logs: {}", response);
-  Ok(response)
+  warn!("The evaluation could not be read as a verdict, asking again.");
+  messages.push(Message { role: Role::Assistant, content: response.clone() });
+  messages.push(Message::user("I could not read that as a verdict. Reply with only the JSON object described above."));
+  let retry = provider.chat_structured(&messages, "report_verdict", &schema).await?;
+  let replies = format!("{}\n\n{}", response, retry);
+  match parse_verdict(&retry) {
+    Evaluation::Verdict(verdict) => Ok((replies, verdict)),
+    Evaluation::Ambiguous(_) => {
+      warn!("The evaluation was still ambiguous, counting the attempt as failed.");
+      let verdict = Verdict {
+        passed: false,
+        reason: "The evaluator's reply could not be read as a verdict.".to_string(),
+        corrected_code: None,
+      };
+      Ok((replies, verdict))
+    }
+  }
 }
 
-fn get_next_prompt(code: &str, logs: &str, user_prompt: &str, passing_response: &str, step: &Value) -> String {
-  let logs = if logs.is_empty() {
-      "[no console log output was produced]".to_string()
+// A standalone prompt for another attempt, for the fresh repair strategy
+fn get_next_prompt(attempt: &Attempt, user_prompt: &str, passing_response: &str, step: &Step) -> String {
+  format!("There is a problem with this code:\n{}\n\nNote that it should be doing exactly what the user wanted, which was '{}'. {}Based on the following logs, the code didn't look like it ran properly: Console logs:\n{}\n\n{}{}It was explained to me that '{}'. Could you write a new, corrected {:?}? Please include the whole file in your response.", attempt.extracted_code, user_prompt, static_errors_section(&attempt.static_errors), logs_or_placeholder(&attempt.console_logs), build_errors_section(&attempt.build_errors), test_suite_section(&attempt.test_suite), passing_response, step.target)
+}
+
+// What happened when the attempt ran, as a tool result turn of the conversation
+fn get_test_results(attempt: &Attempt, reason: &str, step: &Step) -> String {
+  format!("This code was written to {:?} and run:\n{}\n\n{}Console logs:\n{}\n[end of logs]\n\n{}{}The code didn't look like it ran properly, because '{}'.", step.target, attempt.extracted_code, static_errors_section(&attempt.static_errors), logs_or_placeholder(&attempt.console_logs), build_errors_section(&attempt.build_errors), test_suite_section(&attempt.test_suite), reason)
+}
+
+fn logs_or_placeholder(logs: &str) -> &str {
+  if logs.is_empty() {
+      "[no console log output was produced]"
   } else {
-      logs.to_string()
-  };
+      logs
+  }
+}
 
-  format!("There is a problem with this code:\n{}\n\nNote that it should be doing exactly what the user wanted, which was '{}'. Based on the following logs, the code didn't look like it ran properly: Console logs:\n{}\n\nIt was explained to me that '{}'. Could you write a new, corrected {}? Please include the whole file in your response.", code, user_prompt, logs, passing_response, step["target"])
+// Syntax, typecheck and lint errors for a prompt. The page isn't loaded when
+// there are any, so there are no logs to go with them.
+fn static_errors_section(static_errors: &str) -> String {
+  if static_errors.is_empty() {
+      String::new()
+  } else {
+      format!("It was checked before being run and these errors were found:\n{}\n[end of errors]\n\n", static_errors)
+  }
 }
 
-fn is_passing(response: &str) -> bool {
-  response.to_lowercase().contains("yes")
+// The project's test results for a prompt, or nothing when it has no testCommand
+fn test_suite_section(test_suite: &str) -> String {
+  if test_suite.is_empty() {
+      String::new()
+  } else {
+      format!("The project's test suite was run as well: {}\n[end of test results]\n\n", test_suite)
+  }
 }
-fn get_debug_details(trimmed_code: &str, code_attempts: &[String], logs: &[String], passing_responses: &[String]) -> Result<String> {
-  let mut debug_details = String::from("Unable to generate properly working code. Debugging details:");
-  for i in 0..code_attempts.len() {
-      debug_details += &format!(
-          "\n\nChatGPT Response {}:\n{}\n\nConsole logs from test run {}:\n{}\n\nChatGPT evaluation of logs {}:\n\nBased on the following logs, does this code look like it ran properly?\n\n{}",
-          i + 1,
-          code_attempts[i],
-          i + 1,
-          logs[i],
-          i + 1,
-          passing_responses[i]
-      );
+
+// Compiler and bundler errors for a prompt, or nothing when the code built
+fn build_errors_section(build_errors: &str) -> String {
+  if build_errors.is_empty() {
+      String::new()
+  } else {
+      format!("The dev server also reported these build errors, so the page may not be running the new code at all:\n{}\n[end of build errors]\n\n", build_errors)
   }
-  Ok(debug_details)
-}
\ No newline at end of file
+}
+
@@ -0,0 +1,45 @@
+// Runs the plan command end to end with the mock provider
+mod support;
+
+use serde_json::{json, Value};
+use support::{describe, git_repo, run_client, Scratch};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn plan_writes_steps_that_validate() {
+    let repo = git_repo(&[("app/page.js", "export default function Page() {}\n"), ("app/layout.js", "export default function Layout() {}\n")]);
+    let plan = json!({ "steps": [
+        { "description": "Add a counter", "target": "app/counter.js", "context_files": ["app/layout.js"], "test_path": null },
+        { "description": "Show the counter", "target": "app/page.js", "context_files": ["app/counter.js"], "test_path": "http://localhost:3000/" }
+    ] });
+    let work_dir = Scratch::new("work");
+    work_dir.write("feature.json", &json!({ "description": "Count clicks", "llm": { "provider": "mock", "responses": [plan.to_string()] } }).to_string());
+    let repo_dir = repo.path.to_string_lossy().to_string();
+
+    let output = run_client(&work_dir.path, &["--repo-dir", repo_dir.as_str(), "plan", "feature.json", "--output", "planned.json"], &[]).await;
+
+    assert_eq!(output.status.code(), Some(0), "{}", describe(&output));
+    let planned: Value = serde_json::from_str(&work_dir.read("planned.json")).unwrap();
+    assert_eq!(planned["description"], "Count clicks");
+    let steps = planned["steps"].as_array().unwrap();
+    assert_eq!(steps.len(), 2);
+    assert_eq!(steps[0]["target"], "app/counter.js");
+    assert_eq!(steps[0]["files"][1]["filePath"], "app/layout.js");
+    assert_eq!(steps[1]["testPath"], "http://localhost:3000/");
+    let validated = run_client(&work_dir.path, &["validate", "planned.json"], &[]).await;
+    assert_eq!(validated.status.code(), Some(0), "{}", describe(&validated));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn plan_keeps_existing_steps_without_force() {
+    let repo = git_repo(&[("app/page.js", "export default function Page() {}\n")]);
+    let work_dir = Scratch::new("work");
+    let spec = json!({ "description": "Count clicks", "steps": [{ "description": "Already planned", "target": "app/page.js" }] }).to_string();
+    work_dir.write("feature.json", &spec);
+    let repo_dir = repo.path.to_string_lossy().to_string();
+
+    let output = run_client(&work_dir.path, &["--repo-dir", repo_dir.as_str(), "plan", "feature.json"], &[]).await;
+
+    assert_eq!(output.status.code(), Some(2), "{}", describe(&output));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Pass --force"), "{}", describe(&output));
+    assert_eq!(work_dir.read("feature.json"), spec);
+}
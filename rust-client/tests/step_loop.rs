@@ -0,0 +1,235 @@
+// Runs features end to end with the mock provider and every backend pointed
+// at the fake express-autocode-api service
+mod support;
+
+use serde_json::{json, Value};
+use support::{describe, git, git_repo, run_client, FakeApi, Response, Scratch};
+
+const PAGE: &str = "export default function Page() {\n  return <p>Hello</p>;\n}\n";
+const SERVICE_BACKENDS: [&str; 6] = ["--extractor", "service", "--splicer", "service", "--runner", "service"];
+
+// A one-step feature that changes page.js, with scripted model replies
+fn feature(api: &FakeApi, responses: &[&str]) -> Value {
+    json!({
+        "description": "Count clicks",
+        "llm": { "provider": "mock", "responses": responses },
+        "steps": [{
+            "description": "Log the click count",
+            "target": "page.js",
+            "testPath": api.url("/"),
+            "expect": { "logs": ["count is \\d+"] }
+        }]
+    })
+}
+
+async fn run_feature(api: &FakeApi, repo: &Scratch, spec: &Value, extra_args: &[&str]) -> (Scratch, std::process::Output) {
+    let work_dir = Scratch::new("work");
+    work_dir.write("feature.json", &spec.to_string());
+    let repo_dir = repo.path.to_string_lossy().to_string();
+    let api_url = api.url("");
+    let mut args = vec!["--repo-dir", repo_dir.as_str(), "--runs-dir", "runs", "--api-url", api_url.as_str()];
+    if !extra_args.contains(&"--replan-budget") {
+        args.extend(["--replan-budget", "0"]);
+    }
+    args.extend(SERVICE_BACKENDS);
+    args.extend(extra_args);
+    args.extend(["run", "feature.json"]);
+    let output = run_client(&work_dir.path, &args, &[]).await;
+    (work_dir, output)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn passing_step_is_written_and_committed() {
+    let api = FakeApi::start().await;
+    api.on("/api/log-and-run", |_| Response::text("count is 1\n"));
+    let repo = git_repo(&[("page.js", PAGE)]);
+    let reply = "```jsx\nexport default function Page() {\n  console.log('count is 1');\n  return <p>Clicked</p>;\n}\n```";
+
+    let (_work_dir, output) = run_feature(&api, &repo, &feature(&api, &[reply]), &[]).await;
+
+    assert_eq!(output.status.code(), Some(0), "{}", describe(&output));
+    assert!(!String::from_utf8_lossy(&output.stdout).contains("valid feature spec"), "{}", describe(&output));
+    assert_eq!(repo.read("page.js"), "export default function Page() {\n  console.log('count is 1');\n  return <p>Clicked</p>;\n}");
+    let extracted = api.requests_to("/api/extract-jsx");
+    assert_eq!(extracted.len(), 1);
+    assert_eq!(extracted[0].json()["inputString"], reply);
+    let runs = api.requests_to("/api/log-and-run");
+    assert_eq!(runs.len(), 1);
+    assert_eq!(runs[0].query["testPath"], api.url("/"));
+    assert_eq!(runs[0].query["showHTML"], "false");
+    assert!(git(&repo.path, &["log", "-1", "--format=%s"]).starts_with("Step 1: Log the click count"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn failing_step_is_rolled_back_after_its_attempts() {
+    let api = FakeApi::start().await;
+    api.on("/api/log-and-run", |_| Response::text("nothing useful\n"));
+    let repo = git_repo(&[("page.js", PAGE)]);
+    let reply = "```jsx\nexport default function Page() {\n  return <p>Still wrong</p>;\n}\n```";
+
+    let (work_dir, output) = run_feature(&api, &repo, &feature(&api, &[reply, reply]), &["--max-attempts", "2"]).await;
+
+    assert_eq!(output.status.code(), Some(1), "{}", describe(&output));
+    assert_eq!(api.requests_to("/api/log-and-run").len(), 2);
+    assert_eq!(repo.read("page.js"), PAGE);
+    assert!(git(&repo.path, &["status", "--porcelain"]).is_empty());
+    let run_dir = std::fs::read_dir(work_dir.path.join("runs")).unwrap().next().unwrap().unwrap().path();
+    let transcript: Value = serde_json::from_str(&std::fs::read_to_string(run_dir.join("transcript.json")).unwrap()).unwrap();
+    assert_eq!(transcript["passed"], false);
+    assert_eq!(transcript["steps"][0]["attempts"].as_array().unwrap().len(), 2);
+    assert!(transcript["steps"][0]["attempts"][0]["verdict"]["reason"].as_str().unwrap().contains("count is"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn short_reply_is_spliced_by_the_service() {
+    let api = FakeApi::start().await;
+    api.on("/api/log-and-run", |_| Response::text("count is 2\n"));
+    api.on("/api/get-updated-functions", |request| {
+        let existing = request.json()["existingContents"].as_str().unwrap_or_default().to_string();
+        Response::json(json!({ "JSX": format!("{}// spliced\n", existing) }))
+    });
+    let long_page = format!("{}{}", PAGE, (1..=8).map(|i| format!("export const value{} = {};\n", i, i)).collect::<String>());
+    let repo = git_repo(&[("page.js", &long_page)]);
+    let reply = "```jsx\nexport const value1 = 2;\n```";
+
+    let (_work_dir, output) = run_feature(&api, &repo, &feature(&api, &[reply]), &[]).await;
+
+    assert_eq!(output.status.code(), Some(0), "{}", describe(&output));
+    let spliced = api.requests_to("/api/get-updated-functions");
+    assert_eq!(spliced.len(), 1);
+    assert_eq!(spliced[0].json()["existingContents"], long_page);
+    assert_eq!(spliced[0].json()["newContents"], "export const value1 = 2;");
+    assert_eq!(repo.read("page.js"), format!("{}// spliced\n", long_page));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn feature_from_the_api_is_removed_once_it_passes() {
+    let api = FakeApi::start().await;
+    api.on("/api/log-and-run", |_| Response::text("count is 3\n"));
+    let repo = git_repo(&[("page.js", PAGE)]);
+    let reply = "```jsx\nexport default function Page() {\n  console.log('count is 3');\n  return <p>Hello</p>;\n}\n```";
+    let mut spec = feature(&api, &[reply]);
+    spec["docId"] = json!("doc-42");
+    api.on("/api/get-feature", move |_| Response::json(spec.clone()));
+
+    let work_dir = Scratch::new("work");
+    let repo_dir = repo.path.to_string_lossy().to_string();
+    let api_url = api.url("");
+    let mut args = vec!["--repo-dir", repo_dir.as_str(), "--runs-dir", "runs", "--api-url", api_url.as_str()];
+    args.extend(SERVICE_BACKENDS);
+    args.extend(["run", "--from-api"]);
+    let output = run_client(&work_dir.path, &args, &[]).await;
+
+    assert_eq!(output.status.code(), Some(0), "{}", describe(&output));
+    assert_eq!(api.requests_to("/api/get-feature").len(), 1);
+    let removed = api.requests_to("/api/remove-feature");
+    assert_eq!(removed.len(), 1);
+    assert_eq!(removed[0].method, "DELETE");
+    assert_eq!(removed[0].query["doc-id"], "doc-42");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn service_errors_reach_the_model_as_page_errors() {
+    let api = FakeApi::start().await;
+    api.on("/api/log-and-run", |_| Response::status(500));
+    let repo = git_repo(&[("page.js", PAGE)]);
+    let reply = "```jsx\nexport default function Page() {\n  return <p>Hello</p>;\n}\n```";
+
+    let (work_dir, output) = run_feature(&api, &repo, &feature(&api, &[reply]), &["--max-attempts", "1"]).await;
+
+    assert_eq!(output.status.code(), Some(1), "{}", describe(&output));
+    let run_dir = std::fs::read_dir(work_dir.path.join("runs")).unwrap().next().unwrap().unwrap().path();
+    let transcript = std::fs::read_to_string(run_dir.join("transcript.json")).unwrap();
+    assert!(transcript.contains("Failed to log and run. Status code: 500"), "{}", transcript);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn service_is_found_on_the_dotenv_port_and_sent_the_token() {
+    let api = FakeApi::start().await;
+    api.on("/api/log-and-run", |_| Response::text("count is 4\n"));
+    let repo = git_repo(&[("page.js", PAGE)]);
+    let reply = "```jsx\nexport default function Page() {\n  console.log('count is 4');\n  return <p>Hello</p>;\n}\n```";
+    let work_dir = Scratch::new("work");
+    work_dir.write("feature.json", &feature(&api, &[reply]).to_string());
+    work_dir.write(".env", &format!("PORT=\"{}\"\n", api.port));
+
+    let repo_dir = repo.path.to_string_lossy().to_string();
+    let mut args = vec!["--repo-dir", repo_dir.as_str(), "--runs-dir", "runs"];
+    args.extend(SERVICE_BACKENDS);
+    args.extend(["run", "feature.json"]);
+    let output = run_client(&work_dir.path, &args, &[("AUTOCODE_API_TOKEN", "secret")]).await;
+
+    assert_eq!(output.status.code(), Some(0), "{}", describe(&output));
+    let service_calls: Vec<_> = ["/api/extract-jsx", "/api/log-and-run"].iter().flat_map(|path| api.requests_to(path)).collect();
+    assert_eq!(service_calls.len(), 2);
+    for request in service_calls {
+        assert_eq!(request.headers.get("authorization").map(String::as_str), Some("Bearer secret"), "{}", request.path);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn splice_that_does_not_parse_is_not_written() {
+    let api = FakeApi::start().await;
+    api.on("/api/get-updated-functions", |_| Response::json(json!({ "JSX": "export const value1 = (;\n" })));
+    let long_page = format!("{}{}", PAGE, (1..=8).map(|i| format!("export const value{} = {};\n", i, i)).collect::<String>());
+    let repo = git_repo(&[("page.js", &long_page)]);
+    let reply = "```jsx\nexport const value1 = 2;\n```";
+
+    let (work_dir, output) = run_feature(&api, &repo, &feature(&api, &[reply]), &["--max-attempts", "1"]).await;
+
+    assert_eq!(output.status.code(), Some(1), "{}", describe(&output));
+    assert_eq!(repo.read("page.js"), long_page);
+    assert!(api.requests_to("/api/log-and-run").is_empty());
+    let run_dir = std::fs::read_dir(work_dir.path.join("runs")).unwrap().next().unwrap().unwrap().path();
+    let transcript: Value = serde_json::from_str(&std::fs::read_to_string(run_dir.join("transcript.json")).unwrap()).unwrap();
+    let static_errors = transcript["steps"][0]["attempts"][0]["staticErrors"].as_str().unwrap_or_default();
+    assert!(static_errors.starts_with("After splicing the code into the existing page.js"), "{}", transcript);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn step_out_of_attempts_is_replanned_into_steps_that_commit() {
+    let api = FakeApi::start().await;
+    api.on("/api/log-and-run", |_| Response::text("nothing useful\n"));
+    let repo = git_repo(&[("page.js", PAGE)]);
+    let plan = json!({ "steps": [
+        { "description": "Add a click counter", "target": "counter.js", "context_files": [], "test_path": null },
+        { "description": "Show the counter on the page", "target": "page.js", "context_files": ["page.js"], "test_path": api.url("/") }
+    ] })
+    .to_string();
+    let counter = "```jsx\nexport function Counter() {\n  return <button>0</button>;\n}\n```";
+    // The step, the planner and each new step get their own mock provider,
+    // which starts the script over. As code, the plan doesn't parse, so each
+    // step fails its first attempt and writes the counter on its second. That
+    // never logs the count, so the original step fails both, while the new
+    // steps are passed by the evaluator.
+    let responses = [plan.as_str(), counter, "Yes, it does what was asked."];
+
+    let (work_dir, output) = run_feature(&api, &repo, &feature(&api, &responses), &["--max-attempts", "2", "--replan-budget", "1"]).await;
+
+    assert_eq!(output.status.code(), Some(0), "{}", describe(&output));
+    assert!(repo.read("counter.js").contains("export function Counter()"));
+    let subjects = git(&repo.path, &["log", "--format=%s"]);
+    assert_eq!(subjects.lines().collect::<Vec<_>>(), ["Step 2: Show the counter on the page", "Step 1: Add a click counter", "Initial commit"]);
+    let run_dir = std::fs::read_dir(work_dir.path.join("runs")).unwrap().next().unwrap().unwrap().path();
+    let transcript: Value = serde_json::from_str(&std::fs::read_to_string(run_dir.join("transcript.json")).unwrap()).unwrap();
+    assert_eq!(transcript["passed"], true);
+    let steps = transcript["steps"].as_array().unwrap();
+    assert_eq!(steps.len(), 3);
+    assert_eq!(steps[0]["replannedInto"], json!(["Add a click counter", "Show the counter on the page"]));
+    assert_eq!(steps[0]["attempts"].as_array().unwrap().len(), 2);
+    assert_eq!(steps[2]["target"], "page.js");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn invalid_spec_is_not_run() {
+    let api = FakeApi::start().await;
+    let repo = git_repo(&[("page.js", PAGE)]);
+    let mut spec = feature(&api, &[]);
+    spec["steps"][0]["showHTML"] = json!("maybe");
+
+    let (_work_dir, output) = run_feature(&api, &repo, &spec, &[]).await;
+
+    assert_eq!(output.status.code(), Some(2), "{}", describe(&output));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("feature.json: /steps/0/showHTML: must be true, false"), "{}", describe(&output));
+    assert!(api.requests_to("/api/extract-jsx").is_empty());
+}
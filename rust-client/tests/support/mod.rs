@@ -0,0 +1,235 @@
+// An in-process stand-in for express-autocode-api, and helpers for running the
+// client against it
+#![allow(dead_code)]
+
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub query: HashMap<String, String>,
+    // Names are lowercased
+    pub headers: HashMap<String, String>,
+    pub body: String,
+}
+
+impl Request {
+    pub fn json(&self) -> Value {
+        serde_json::from_str(&self.body).unwrap_or(Value::Null)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+impl Response {
+    pub fn json(value: Value) -> Self {
+        Response { status: 200, content_type: "application/json", body: value.to_string() }
+    }
+
+    pub fn text(body: &str) -> Self {
+        Response { status: 200, content_type: "text/plain", body: body.to_string() }
+    }
+
+    pub fn status(status: u16) -> Self {
+        Response { status, content_type: "text/plain", body: String::new() }
+    }
+}
+
+type Handler = Arc<dyn Fn(&Request) -> Response + Send + Sync>;
+
+// Answers the five endpoints with a handler per path, and anything else, such
+// as a step's testPath, with an empty page. Every request is kept for asserts.
+pub struct FakeApi {
+    pub port: u16,
+    handlers: Arc<Mutex<HashMap<String, Handler>>>,
+    requests: Arc<Mutex<Vec<Request>>>,
+    task: JoinHandle<()>,
+}
+
+impl FakeApi {
+    // Starts with handlers that act like a service with nothing to report:
+    // code is extracted by dropping fence lines, splicing keeps the new
+    // contents, pages log nothing, there is no feature and removing one works
+    pub async fn start() -> FakeApi {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.expect("bind the fake service");
+        let port = listener.local_addr().expect("fake service address").port();
+        let handlers: Arc<Mutex<HashMap<String, Handler>>> = Arc::default();
+        let requests: Arc<Mutex<Vec<Request>>> = Arc::default();
+        let task = tokio::spawn(serve(listener, handlers.clone(), requests.clone()));
+        let api = FakeApi { port, handlers, requests, task };
+
+        api.on("/api/extract-jsx", |request| {
+            let input = request.json()["inputString"].as_str().unwrap_or_default().to_string();
+            let code: Vec<&str> = input.lines().filter(|line| !line.contains("```")).collect();
+            Response::json(json!({ "JSX": code.join("\n") }))
+        });
+        api.on("/api/get-updated-functions", |request| Response::json(json!({ "JSX": request.json()["newContents"] })));
+        api.on("/api/log-and-run", |_| Response::text(""));
+        api.on("/api/get-feature", |_| Response::status(404));
+        api.on("/api/remove-feature", |_| Response::status(200));
+        api
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("http://127.0.0.1:{}{}", self.port, path)
+    }
+
+    // Replaces how `path` is answered
+    pub fn on(&self, path: &str, handler: impl Fn(&Request) -> Response + Send + Sync + 'static) {
+        self.handlers.lock().unwrap().insert(path.to_string(), Arc::new(handler));
+    }
+
+    pub fn requests_to(&self, path: &str) -> Vec<Request> {
+        self.requests.lock().unwrap().iter().filter(|request| request.path == path).cloned().collect()
+    }
+}
+
+impl Drop for FakeApi {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn serve(listener: TcpListener, handlers: Arc<Mutex<HashMap<String, Handler>>>, requests: Arc<Mutex<Vec<Request>>>) {
+    while let Ok((stream, _)) = listener.accept().await {
+        let handlers = handlers.clone();
+        let requests = requests.clone();
+        tokio::spawn(async move {
+            let _ = answer(stream, handlers, requests).await;
+        });
+    }
+}
+
+// Reads one HTTP/1.1 request and answers it, closing the connection after
+async fn answer(stream: TcpStream, handlers: Arc<Mutex<HashMap<String, Handler>>>, requests: Arc<Mutex<Vec<Request>>>) -> std::io::Result<()> {
+    let mut reader = BufReader::new(stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).await?;
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_string();
+    let target = parts.next().unwrap_or("/").to_string();
+
+    let mut headers = HashMap::new();
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header).await? == 0 || header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            headers.insert(name.trim().to_ascii_lowercase(), value.trim().to_string());
+        }
+    }
+    let content_length = headers.get("content-length").and_then(|length| length.parse().ok()).unwrap_or(0);
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body).await?;
+
+    let url = url::Url::parse(&format!("http://fake{}", target)).expect("request target");
+    let request = Request {
+        method,
+        path: url.path().to_string(),
+        query: url.query_pairs().map(|(name, value)| (name.to_string(), value.to_string())).collect(),
+        headers,
+        body: String::from_utf8_lossy(&body).to_string(),
+    };
+    requests.lock().unwrap().push(request.clone());
+    let handler = handlers.lock().unwrap().get(&request.path).cloned();
+    let response = match handler {
+        Some(handler) => handler(&request),
+        None => Response { status: 200, content_type: "text/html", body: "<html><body></body></html>".to_string() },
+    };
+
+    let head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status, if response.status < 400 { "OK" } else { "Error" }, response.content_type, response.body.len()
+    );
+    let mut stream = reader.into_inner();
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(response.body.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+// A directory under the system temp dir, removed when dropped
+pub struct Scratch {
+    pub path: PathBuf,
+}
+
+impl Scratch {
+    pub fn new(name: &str) -> Scratch {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!("autocode-{}-{}-{}", name, std::process::id(), COUNT.fetch_add(1, Ordering::SeqCst)));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).expect("create scratch directory");
+        Scratch { path }
+    }
+
+    pub fn write(&self, relative: &str, contents: &str) -> PathBuf {
+        let path = self.path.join(relative);
+        fs::create_dir_all(path.parent().unwrap()).expect("create parent directory");
+        fs::write(&path, contents).expect("write file");
+        path
+    }
+
+    pub fn read(&self, relative: &str) -> String {
+        fs::read_to_string(self.path.join(relative)).unwrap_or_default()
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+// A git repository holding `files` in one commit
+pub fn git_repo(files: &[(&str, &str)]) -> Scratch {
+    let repo = Scratch::new("repo");
+    for (path, contents) in files {
+        repo.write(path, contents);
+    }
+    git(&repo.path, &["init", "-q", "-b", "main"]);
+    git(&repo.path, &["add", "-A"]);
+    git(&repo.path, &["-c", "user.name=Test", "-c", "user.email=test@example.com", "commit", "-q", "-m", "Initial commit"]);
+    repo
+}
+
+pub fn git(repo_dir: &Path, args: &[&str]) -> String {
+    let output = Command::new("git").args(args).current_dir(repo_dir).output().expect("run git");
+    assert!(output.status.success(), "git {:?} failed: {}", args, String::from_utf8_lossy(&output.stderr));
+    String::from_utf8_lossy(&output.stdout).to_string()
+}
+
+// Runs the client binary in `work_dir`, so its .env and runs directory stay
+// there. Only `env` and the work dir's .env say where the service is.
+pub async fn run_client(work_dir: &Path, args: &[&str], env: &[(&str, &str)]) -> Output {
+    tokio::process::Command::new(env!("CARGO_BIN_EXE_autocode-native"))
+        .args(args)
+        .current_dir(work_dir)
+        .env_remove("CLONING")
+        .env_remove("PORT")
+        .env_remove("AUTOCODE_API_URL")
+        .env_remove("AUTOCODE_API_TOKEN")
+        .envs(env.iter().copied())
+        .output()
+        .await
+        .expect("run autocode-native")
+}
+
+// The output of a run, for assertion messages
+pub fn describe(output: &Output) -> String {
+    format!("exit {:?}\nstdout:\n{}\nstderr:\n{}", output.status.code(), String::from_utf8_lossy(&output.stdout), String::from_utf8_lossy(&output.stderr))
+}